@@ -8,8 +8,9 @@ extern crate cortex;
 // extern crate libxml;
 extern crate zmq;
 
-use cortex::manager::TaskManager;
+use cortex::manager::{TaskManager, VentilatorStats};
 use cortex::backend::DEFAULT_DB_ADDRESS;
+use std::sync::Arc;
 
 /// A dispatcher executable for CorTeX distributed processing with ZMQ
 fn main() {
@@ -20,7 +21,13 @@ fn main() {
     //   (typically specified in /etc/postgresql/9.1/main/postgresql.conf or similar)
     queue_size : 800, // If we have 400 CPUs, this is allows us two task dispatches before reload, should be fine.
     message_size : 100000,
-    backend_address : DEFAULT_DB_ADDRESS.clone().to_string()
+    backend_address : DEFAULT_DB_ADDRESS.clone().to_string(),
+    maintenance_interval : 60,
+    task_timeout : 3600,
+    send_payload : true,
+    acknowledge_results : false,
+    discard_output : false,
+    ventilator_stats : Arc::new(VentilatorStats::default())
   };
   let job_limit = None;
   manager.start(job_limit).unwrap();
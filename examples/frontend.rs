@@ -279,7 +279,8 @@ fn main() {
       entry: String::new(),
       corpusid : 0,
       serviceid : 0,
-      status : 0
+      status : 0,
+      metadata : None
     };
     let backend = Backend::default();
     let task = backend.sync(&placeholder_task).unwrap_or(placeholder_task); // TODO: Error-reporting
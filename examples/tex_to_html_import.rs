@@ -15,11 +15,12 @@ use std::env;
 // use std::io::Read;
 // use std::io::Error;
 
+use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 use cortex::backend::{Backend, DEFAULT_DB_ADDRESS};
 use cortex::data::{Task, TaskStatus, Service};
-use cortex::manager::TaskManager;
+use cortex::manager::{TaskManager, VentilatorStats};
 use cortex::worker::InitWorker;
 use pericortex::worker::Worker;
 
@@ -37,7 +38,7 @@ fn main() {
   };
   println!("-- Importing {:?} at {:?} ...",corpus_name, corpus_path.clone());
   let backend = Backend::default();
-  if backend.needs_init() {
+  if backend.needs_init().unwrap() {
     println!("-- Backend not initialized, seting up tables.");
     backend.setup_task_tables().unwrap();
   }
@@ -48,7 +49,8 @@ fn main() {
       entry : corpus_path.clone(),
       serviceid : 1, // Init service always has id 1
       corpusid : 1,
-      status : TaskStatus::TODO.raw()
+      status : TaskStatus::TODO.raw(),
+      metadata : None
     }).unwrap();
 
   // Let us thread out a ventilator on a special port
@@ -59,7 +61,13 @@ fn main() {
       result_port : 5758,
       queue_size : 100000,
       message_size : 100,
-      backend_address : DEFAULT_DB_ADDRESS.clone().to_string()
+      backend_address : DEFAULT_DB_ADDRESS.clone().to_string(),
+      maintenance_interval : 60,
+      task_timeout : 3600,
+      send_payload : true,
+      acknowledge_results : false,
+      discard_output : false,
+      ventilator_stats : Arc::new(VentilatorStats::default())
     };
     assert!(manager.start(job_limit).is_ok());
   });
@@ -8,32 +8,59 @@
 //! Data structures and traits for each framework component in the Task store
 
 use rustc_serialize::json::{Json, ToJson};
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::File;
 use std::path::Path;
 use std::str;
 use regex::Regex;
+use time;
 
 use postgres::Connection;
 use postgres::rows::{Row};
-use postgres::error::Error;
 
 use Archive::*;
+use error::CortexError;
+use backend::Backend;
 
 /// A minimalistic ORM trait for CorTeX data items
 pub trait CortexORM {
   /// Select from Task store via the primary id
-  fn select_by_id<'a>(&'a self, connection: &'a Connection) -> Result<Option<Self>, Error> where Self: Sized;
+  fn select_by_id<'a>(&'a self, connection: &'a Connection) -> Result<Option<Self>, CortexError> where Self: Sized;
   /// Select from Task store via a struct-specific uniquely identifying key
-  fn select_by_key<'a>(&'a self, connection : &'a Connection) -> Result<Option<Self>,Error> where Self: Sized;
+  fn select_by_key<'a>(&'a self, connection : &'a Connection) -> Result<Option<Self>, CortexError> where Self: Sized;
   /// Inser the row identified by this struct into the Task store (overwrite if present)
-  fn insert(&self, connection: &Connection) -> Result<(),Error>;
+  fn insert(&self, connection: &Connection) -> Result<(), CortexError>;
   /// Delete the row identified by this struct from the Task store
-  fn delete(&self, connection: &Connection) -> Result<(),Error>;
+  fn delete(&self, connection: &Connection) -> Result<(), CortexError>;
   /// Construct a struct from a given Task store row
   fn from_row(row : Row) -> Self;
   /// Obtain the id of the struct, if any
   fn get_id(&self) -> Option<i32>;
+  /// Check the struct is well-formed, before it is added to the Task store
+  fn validate(&self, _formats : &FormatRegistry) -> Result<(), CortexError> { Ok(()) }
+}
+
+/// The file formats services are known to consume and produce, guarding against typos in service declarations
+pub struct FormatRegistry {
+  formats : HashSet<String>
+}
+impl Default for FormatRegistry {
+  fn default() -> FormatRegistry {
+    FormatRegistry {
+      formats : ["tex", "html", "xhtml", "xml", "jats", "pdf", "epub", "zip", "txt", "log", "bbl"]
+        .iter().map(|&format| format.to_string()).collect()
+    }
+  }
+}
+impl FormatRegistry {
+  /// Registers an additional known format
+  pub fn register(&mut self, format : &str) {
+    self.formats.insert(format.to_string());
+  }
+  /// Is this a known format?
+  pub fn contains(&self, format : &str) -> bool {
+    self.formats.contains(format)
+  }
 }
 
 
@@ -51,7 +78,9 @@ pub struct Task {
   /// id of the corpus hosting this task
   pub corpusid: i32,
   /// current processing status of this task
-  pub status: i32
+  pub status: i32,
+  /// optional free-form metadata (e.g. source language, arXiv category), stored as jsonb
+  pub metadata: Option<Json>
 }
 impl fmt::Display for Task {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -77,6 +106,18 @@ impl fmt::Debug for Task {
         write!(f, "(taskid: {},\n\tentry: {},\n\tserviceid: {},\n\tcorpusid: {},\n\t status: {})\n", taskid, self.entry, self.serviceid, self.corpusid, self.status)
     }
 }
+impl ToJson for Task {
+    fn to_json(&self) -> Json {
+        let mut map = BTreeMap::new();
+        map.insert("id".to_string(), self.id.to_json());
+        map.insert("entry".to_string(), self.entry.to_json());
+        map.insert("serviceid".to_string(), self.serviceid.to_json());
+        map.insert("corpusid".to_string(), self.corpusid.to_json());
+        map.insert("status".to_string(), self.status.to_json());
+        map.insert("metadata".to_string(), self.metadata.to_json());
+        Json::Object(map)
+    }
+}
 impl CortexORM for Task {
   fn get_id(&self) -> Option<i32> {
     // TODO: Best way to deal with this?
@@ -85,8 +126,8 @@ impl CortexORM for Task {
       None => None
     }
   }
-  fn select_by_id<'a>(&'a self, connection : &'a Connection) -> Result<Option<Task>,Error> {
-    let stmt = try!(connection.prepare("SELECT taskid,entry,serviceid,corpusid,status FROM tasks WHERE taskid = $1"));
+  fn select_by_id<'a>(&'a self, connection : &'a Connection) -> Result<Option<Task>, CortexError> {
+    let stmt = try!(connection.prepare("SELECT taskid,entry,serviceid,corpusid,status,metadata::text AS metadata FROM tasks WHERE taskid = $1"));
     let rows = try!(stmt.query(&[&self.id]));
     if rows.len() > 0 {
       let row = rows.get(0);
@@ -95,8 +136,8 @@ impl CortexORM for Task {
       Ok(None)
     }
   }
-  fn select_by_key<'a>(&'a self, connection : &'a Connection) -> Result<Option<Task>,Error> {
-    let stmt = try!(connection.prepare("SELECT taskid,entry,serviceid,corpusid,status FROM tasks WHERE entry = $1 and serviceid = $2 and corpusid = $3"));
+  fn select_by_key<'a>(&'a self, connection : &'a Connection) -> Result<Option<Task>, CortexError> {
+    let stmt = try!(connection.prepare("SELECT taskid,entry,serviceid,corpusid,status,metadata::text AS metadata FROM tasks WHERE entry = $1 and serviceid = $2 and corpusid = $3"));
     let rows = try!(stmt.query(&[&self.entry, &self.serviceid, &self.corpusid]));
     if rows.len() > 0 {
       let row = rows.get(0);
@@ -105,10 +146,11 @@ impl CortexORM for Task {
       Ok(None)
     }
   }
-  fn insert(&self, connection : &Connection) -> Result<(), Error> {
-    try!(connection.execute("INSERT INTO tasks (serviceid, corpusid, status, entry) values($1, $2, $3, $4)", &[&self.serviceid, &self.corpusid, &self.status, &self.entry]));
+  fn insert(&self, connection : &Connection) -> Result<(), CortexError> {
+    let metadata_text = self.metadata.as_ref().map(|metadata| metadata.to_string());
+    try!(connection.execute("INSERT INTO tasks (serviceid, corpusid, status, entry, metadata) values($1, $2, $3, $4, $5::text::jsonb)", &[&self.serviceid, &self.corpusid, &self.status, &self.entry, &metadata_text]));
     Ok(()) }
-  fn delete(&self, connection: &Connection) -> Result<(),Error> {
+  fn delete(&self, connection: &Connection) -> Result<(), CortexError> {
     try!(connection.execute("DELETE FROM tasks WHERE taskid = $1", &[&self.id])); 
     Ok(()) 
   }
@@ -116,20 +158,50 @@ impl CortexORM for Task {
     let fix_width_entry : String = row.get(1);
     Task {
       id : Some(row.get(0)),
-      entry : fix_width_entry.trim_right().to_string(),
+      entry : Backend::task_entry_trim(&fix_width_entry),
       serviceid : row.get(2),
       corpusid : row.get(3),
-      status : row.get(4)
+      status : row.get(4),
+      // Note: metadata is only selected (as text) by queries that need it
+      metadata : match row.get_opt::<_, Option<String>>("metadata") {
+        Ok(Some(metadata_text)) => Json::from_str(&metadata_text).ok(),
+        _ => None
+      }
     }
   }
 }
 
 impl Task {
+  /// Constructs a new, queued, task for an entry of a `Corpus` to be processed by a `Service`
+  pub fn new(entry : &str, service : &Service, corpus : &Corpus) -> Task {
+    Task {
+      id : None,
+      entry : entry.to_string(),
+      serviceid : service.id.unwrap(),
+      corpusid : corpus.id.unwrap(),
+      status : TaskStatus::TODO.raw(),
+      metadata : None
+    }
+  }
+  /// Constructs a new task recording an imported entry of a `Corpus`
+  /// (owned by the import service, with the import already successful)
+  pub fn imported(entry : &str, corpus : &Corpus) -> Task {
+    Task {
+      id : None,
+      entry : entry.to_string(),
+      serviceid : 2, // Import service always has id 2
+      corpusid : corpus.id.unwrap(),
+      status : TaskStatus::NoProblem.raw(),
+      metadata : None
+    }
+  }
   /// Generates a `TaskReport`, given the path to a result archive from a CorTeX processing job
   /// Expects a "cortex.log" file in the archive, following the LaTeXML messaging conventions
+  /// The extensions of the other archive entries are recorded as the report's artifact formats
   pub fn generate_report(&self, result: &Path) -> TaskReport {
     // println!("Preparing report for {:?}, result at {:?}",self.entry, result);
     let mut messages = Vec::new();
+    let mut artifact_formats = Vec::new();
     let mut status = TaskStatus::Fatal; // Fatal by default
 
     { // -- Archive::Reader, trying to localize (to .drop asap)
@@ -149,6 +221,9 @@ impl Task {
           Ok(e) => {
             let current_name = e.pathname();
             if current_name != log_name {
+              if let Some(extension) = Path::new(&current_name).extension().and_then(|extension| extension.to_str()) {
+                artifact_formats.push(extension.to_lowercase());
+              }
               continue;
             } else {
               // In a "raw" read, we don't know the data size in advance. So we bite the bullet and
@@ -189,11 +264,16 @@ impl Task {
       }
     }
     } // -- END: Archive::Reader, trying to localize (to .drop asap)
+    artifact_formats.sort();
+    artifact_formats.dedup();
 
     TaskReport {
       task : self.clone(),
       status : status,
-      messages : messages
+      messages : messages,
+      duration_ms : None,
+      worker_id : None,
+      artifact_formats : artifact_formats
     }
   }
 
@@ -262,13 +342,24 @@ impl Task {
   }
 
   /// Returns an open file handle to the task's entry
-  pub fn prepare_input_stream(&self) -> Result<File, Error> {
+  pub fn prepare_input_stream(&self) -> Result<File, CortexError> {
     let entry_path = Path::new(&self.entry);
     let file = try!(File::open(entry_path));
     Ok(file)
   }
 }
 
+#[derive(Clone, Debug)]
+/// A task fetched for dispatch, bundled with what a worker needs to process it, see `Backend::fetch_dispatched`
+pub struct DispatchedTask {
+  /// the dispatched `Task`
+  pub task : Task,
+  /// the `Service` the task is processed by, e.g. for its formats and input converter
+  pub service : Service,
+  /// the number of this processing attempt, 1 for a first run (reruns increase it)
+  pub attempt : i32
+}
+
 #[derive(Clone)]
 /// In-progress task, with dispatch metadata
 pub struct TaskProgress {
@@ -277,13 +368,26 @@ pub struct TaskProgress {
   /// time of entering the job queue / first dispatch
   pub created_at : i64,
   /// number of dispatch retries
-  pub retries : i64
+  pub retries : i64,
+  /// identity of the worker the task was dispatched to, if known
+  pub worker_id : Option<String>,
+  /// milliseconds since the epoch when the task was last sent to a worker, if it was
+  pub dispatched_at_ms : Option<i64>
 }
 impl TaskProgress {
   /// What is the latest admissible time for this task to be completed?
   pub fn expected_at(&self) -> i64 {
     self.created_at + ((self.retries + 1)*3600)
   }
+  /// Milliseconds elapsed since the task was sent to a worker, i.e. its conversion time once its result is back
+  pub fn dispatch_duration_ms(&self) -> Option<i32> {
+    self.dispatched_at_ms.map(|dispatched_at_ms| (TaskProgress::now_ms() - dispatched_at_ms) as i32)
+  }
+  /// The current time, in milliseconds since the epoch
+  pub fn now_ms() -> i64 {
+    let now = time::get_time();
+    now.sec * 1000 + (now.nsec / 1000000) as i64
+  }
 }
 
 #[derive(Clone)]
@@ -294,7 +398,13 @@ pub struct TaskReport {
   /// the reported processing status
   pub status : TaskStatus,
   /// a vector of `TaskMessage` log entries
-  pub messages : Vec<TaskMessage>
+  pub messages : Vec<TaskMessage>,
+  /// wall-clock processing time, from dispatch to receiving the result, if known
+  pub duration_ms : Option<i32>,
+  /// identity of the worker which completed the task, if known
+  pub worker_id : Option<String>,
+  /// the formats of the artifacts produced for the task, as found in its result archive
+  pub artifact_formats : Vec<String>
 }
 
 #[derive(Clone)]
@@ -324,6 +434,8 @@ pub enum TaskStatus {
   Fatal,
   /// currently queued for processing
   TODO,
+  /// cancelled while in progress, any late results are discarded
+  Cancelled,
   /// currently blocked by dependencies
   Blocked(i32),
   /// currently being processed (marker identifies batch)
@@ -340,6 +452,9 @@ impl fmt::Debug for TaskMessage {
     write!(f, "(severity: {}, category: {},\n\twhat: {},\n\tdetails: {})\n", self.severity, self.category, self.what, self.details)
   }
 }
+/// Raw Task store value of `TaskStatus::Cancelled`, set apart from the blocked (below -5) values in use,
+/// which include the transient marks of `mark_rerun` (down to -65535)
+const CANCELLED_RAW : i32 = -1000000;
 impl TaskStatus {
   /// Maps the enumeration into the raw ints for the Task store
   pub fn raw(&self) -> i32 {
@@ -349,6 +464,7 @@ impl TaskStatus {
       &TaskStatus::Error => -3,
       &TaskStatus::Fatal => -4,
       &TaskStatus::TODO => -5,
+      &TaskStatus::Cancelled => CANCELLED_RAW,
       &TaskStatus::Blocked(x) => x,
       &TaskStatus::Queued(x) => x
     }
@@ -361,6 +477,7 @@ impl TaskStatus {
       &TaskStatus::Error => "error",
       &TaskStatus::Fatal => "fatal",
       &TaskStatus::TODO => "todo",
+      &TaskStatus::Cancelled => "cancelled",
       &TaskStatus::Blocked(_) => "blocked",
       &TaskStatus::Queued(_) => "queued"
     }.to_string()
@@ -373,6 +490,7 @@ impl TaskStatus {
       -3 => TaskStatus::Error,
       -4 => TaskStatus::Fatal,
       -5 => TaskStatus::TODO,
+      CANCELLED_RAW => TaskStatus::Cancelled,
       num if num < -5 => TaskStatus::Blocked(num.clone()),
       _ => TaskStatus::Queued(num.clone())
     }
@@ -385,14 +503,28 @@ impl TaskStatus {
       "error" => TaskStatus::Error,
       "fatal" => TaskStatus::Fatal,
       "todo" => TaskStatus::TODO,
+      "cancelled" => TaskStatus::Cancelled,
       "blocked" => TaskStatus::Blocked(-6),
       "queued" => TaskStatus::Queued(1),
       _ => TaskStatus::Fatal
     }
   }
+  /// Canonical report ordering of the statuses, from most to least severe
+  pub fn severity_rank(&self) -> usize {
+    match self {
+      &TaskStatus::Fatal => 0,
+      &TaskStatus::Error => 1,
+      &TaskStatus::Warning => 2,
+      &TaskStatus::NoProblem => 3,
+      &TaskStatus::TODO => 4,
+      &TaskStatus::Cancelled => 5,
+      &TaskStatus::Blocked(_) => 6,
+      &TaskStatus::Queued(_) => 7
+    }
+  }
   /// Returns all raw severity strings as a vector
   pub fn keys() -> Vec<String> {
-    ["no_problem", "warning", "error", "fatal", "todo", "blocked", "queued"].iter().map(|&x| x.to_string()).collect::<Vec<_>>()
+    ["no_problem", "warning", "error", "fatal", "todo", "cancelled", "blocked", "queued"].iter().map(|&x| x.to_string()).collect::<Vec<_>>()
   }
 }
 
@@ -423,7 +555,7 @@ impl ToJson for Corpus {
 
 impl CortexORM for Corpus {
   fn get_id(&self) -> Option<i32> {self.id}
-  fn select_by_id<'a>(&'a self, connection : &'a Connection) -> Result<Option<Corpus>,Error> {
+  fn select_by_id<'a>(&'a self, connection : &'a Connection) -> Result<Option<Corpus>, CortexError> {
     let stmt = try!(connection.prepare("SELECT corpusid,name,path,complex FROM corpora WHERE corpusid = $1"));
     let rows = try!(stmt.query(&[&self.id]));
     if rows.len() > 0 {
@@ -433,8 +565,8 @@ impl CortexORM for Corpus {
       Ok(None)
     }
   }
-  fn select_by_key<'a>(&'a self, connection : &'a Connection) -> Result<Option<Corpus>,Error> {
-    let stmt = try!(connection.prepare("SELECT corpusid,name,path,complex FROM corpora WHERE name = $1"));
+  fn select_by_key<'a>(&'a self, connection : &'a Connection) -> Result<Option<Corpus>, CortexError> {
+    let stmt = try!(connection.prepare("SELECT corpusid,name,path,complex FROM corpora WHERE name = $1 and deleted_at IS NULL"));
     let rows = try!(stmt.query(&[&self.name]));
     if rows.len() > 0 {
       let row = rows.get(0);
@@ -443,10 +575,10 @@ impl CortexORM for Corpus {
       Ok(None)
     }
   }
-  fn insert(&self, connection : &Connection) -> Result<(), Error> {
+  fn insert(&self, connection : &Connection) -> Result<(), CortexError> {
     try!(connection.execute("INSERT INTO corpora (name, path, complex) values($1, $2, $3)", &[&self.name, &self.path, &self.complex]));
     Ok(()) }
-  fn delete(&self, connection: &Connection) -> Result<(),Error> {
+  fn delete(&self, connection: &Connection) -> Result<(), CortexError> {
     try!(connection.execute("DELETE FROM tasks WHERE corpusid = $1", &[&self.id])); 
     try!(connection.execute("DELETE FROM corpora WHERE corpusid = $1", &[&self.id])); 
     Ok(()) 
@@ -461,8 +593,19 @@ impl CortexORM for Corpus {
   }
 }
 impl Corpus {
+  /// Select a corpus from the Task store, given its name
+  pub fn from_name(connection : &Connection, name : String) -> Result<Option<Self>, CortexError> {
+    let stmt = try!(connection.prepare("SELECT corpusid,name,path,complex FROM corpora WHERE name = $1 and deleted_at IS NULL"));
+    let rows = try!(stmt.query(&[&name]));
+    if rows.len() == 1 {
+      let row = rows.get(0);
+      Ok(Some(Corpus::from_row(row)))
+    } else {
+      Ok(None)
+    }
+  }
   /// Return a vector of services currently activated on this corpus
-  pub fn select_services<'a>(&'a self, connection : &'a Connection) -> Result<Vec<Service>,Error> {
+  pub fn select_services<'a>(&'a self, connection : &'a Connection) -> Result<Vec<Service>, CortexError> {
     let stmt = try!(connection.prepare("SELECT distinct(serviceid) FROM tasks WHERE corpusid = $1"));
     let rows = try!(stmt.query(&[&self.id]));
     let mut services = Vec::new();
@@ -488,7 +631,7 @@ impl Corpus {
   }
 }
 
-#[derive(Clone)]
+#[derive(RustcEncodable, Clone)]
 /// A CorTeX processing Service
 pub struct Service {
   /// optional id (None for mock / yet-to-be-inserted rows)
@@ -499,7 +642,8 @@ pub struct Service {
   pub version : f32,
   /// the expected input format for this service (e.g. tex)
   pub inputformat : String,
-  /// the produced output format by this service (e.g. html)
+  /// the produced output format by this service (e.g. html),
+  /// or a comma-separated list when several artifacts are produced (e.g. html,log,bbl)
   pub outputformat : String,
   // pub xpath : String,
   // pub resource : String,
@@ -529,7 +673,10 @@ impl fmt::Debug for Service {
 
 impl CortexORM for Service {
   fn get_id(&self) -> Option<i32> {self.id}
-  fn select_by_id<'a>(&'a self, connection : &'a Connection) -> Result<Option<Service>,Error> {
+  fn validate(&self, formats : &FormatRegistry) -> Result<(), CortexError> {
+    self.validate_formats(formats)
+  }
+  fn select_by_id<'a>(&'a self, connection : &'a Connection) -> Result<Option<Service>, CortexError> {
     let stmt =  try!(connection.prepare("SELECT serviceid,name,version,inputformat,outputformat,inputconverter,complex FROM services WHERE serviceid = $1"));
     let rows = try!(stmt.query(&[&self.id]));
     if rows.len() > 0 {
@@ -539,8 +686,8 @@ impl CortexORM for Service {
       Ok(None)
     }
   }
-  fn select_by_key<'a>(&'a self, connection : &'a Connection) -> Result<Option<Service>,Error> {
-    let stmt =  try!(connection.prepare("SELECT serviceid,name,version,inputformat,outputformat,inputconverter,complex FROM services WHERE name = $1 and version = $2"));
+  fn select_by_key<'a>(&'a self, connection : &'a Connection) -> Result<Option<Service>, CortexError> {
+    let stmt =  try!(connection.prepare("SELECT serviceid,name,version,inputformat,outputformat,inputconverter,complex FROM services WHERE name = $1 and version = $2 and deleted_at IS NULL"));
     let rows = try!(stmt.query(&[&self.name, &self.version]));
     if rows.len() > 0 {
       let row = rows.get(0);
@@ -549,11 +696,16 @@ impl CortexORM for Service {
       Ok(None)
     }
   }
-  fn insert(&self, connection : &Connection) -> Result<(), Error> {
-    try!(connection.execute("INSERT INTO services (name, version, inputformat, outputformat, inputconverter, complex) values($1, $2, $3, $4, $5, $6)",
+  fn insert(&self, connection : &Connection) -> Result<(), CortexError> {
+    let inserted = try!(connection.execute("INSERT INTO services (name, version, inputformat, outputformat, inputconverter, complex) values($1, $2, $3, $4, $5, $6)
+       ON CONFLICT (name, version) DO NOTHING",
        &[&self.name, &self.version, &self.inputformat, &self.outputformat, &self.inputconverter, &self.complex]));
+    if inserted == 0 {
+      // A service is uniquely identified by its name and version
+      return Err(CortexError::AlreadyExists(format!("service {} (version {})", self.name, self.version)))
+    }
     Ok(()) }
-  fn delete(&self, connection: &Connection) -> Result<(),Error> {
+  fn delete(&self, connection: &Connection) -> Result<(), CortexError> {
     try!(connection.execute("DELETE FROM tasks WHERE serviceid = $1", &[&self.id])); 
     try!(connection.execute("DELETE FROM services WHERE serviceid = $1", &[&self.id])); 
     Ok(()) 
@@ -572,8 +724,8 @@ impl CortexORM for Service {
 }
 impl Service {
   /// Select a service from the Task store via its human-readable name. Requires a postgres `Connection`.
-  pub fn from_name(connection : &Connection, name : String) -> Result<Option<Self>, Error> { 
-    let stmt =  try!(connection.prepare("SELECT serviceid,name,version,inputformat,outputformat,inputconverter,complex FROM services WHERE name = $1"));
+  pub fn from_name(connection : &Connection, name : String) -> Result<Option<Self>, CortexError> { 
+    let stmt =  try!(connection.prepare("SELECT serviceid,name,version,inputformat,outputformat,inputconverter,complex FROM services WHERE name = $1 and deleted_at IS NULL"));
     let rows = try!(stmt.query(&[&name]));
     if rows.len() == 1 {
       let row = rows.get(0);
@@ -582,6 +734,22 @@ impl Service {
       Ok(None)
     }
   }
+  /// Returns the list of output formats produced by this service
+  pub fn output_formats(&self) -> Vec<String> {
+    self.outputformat.split(',')
+      .map(|format| format.trim().to_string())
+      .filter(|format| !format.is_empty())
+      .collect()
+  }
+  /// Checks the input and output formats of this service are all known to the `formats` registry
+  pub fn validate_formats(&self, formats : &FormatRegistry) -> Result<(), CortexError> {
+    let mut service_formats = self.output_formats();
+    service_formats.push(self.inputformat.clone());
+    match service_formats.iter().find(|format| !formats.contains(format)) {
+      Some(unknown_format) => Err(CortexError::Invalid(format!("unknown format {} in service {}", unknown_format, self.name))),
+      None => Ok(())
+    }
+  }
   /// Returns a hash representation of the `Service`, usually for frontend reports
   pub fn to_hash(&self) -> HashMap<String, String> {
     let mut hm = HashMap::new();
@@ -0,0 +1,74 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A unified error type for the Task store, the message queues and the file system
+
+use std::error;
+use std::fmt;
+use std::io;
+use postgres;
+use zmq;
+
+#[derive(Debug)]
+/// An enumeration of the failure modes of the CorTeX framework
+pub enum CortexError {
+  /// Task store (postgres) failure
+  Postgres(postgres::error::Error),
+  /// message queue (zeromq) failure
+  Zmq(zmq::Error),
+  /// file system failure
+  Io(io::Error),
+  /// malformed or unexpected message between the dispatcher and its workers
+  Protocol(String),
+  /// a uniquely identified Task store item which is already present
+  AlreadyExists(String),
+  /// an argument outside of the values accepted by the Task store
+  Invalid(String)
+}
+
+impl fmt::Display for CortexError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      &CortexError::Postgres(ref e) => write!(f, "Task store error: {}", e),
+      &CortexError::Zmq(ref e) => write!(f, "Message queue error: {:?}", e),
+      &CortexError::Io(ref e) => write!(f, "I/O error: {}", e),
+      &CortexError::Protocol(ref message) => write!(f, "Protocol error: {}", message),
+      &CortexError::AlreadyExists(ref item) => write!(f, "Already exists: {}", item),
+      &CortexError::Invalid(ref message) => write!(f, "Invalid argument: {}", message)
+    }
+  }
+}
+
+impl error::Error for CortexError {
+  fn description(&self) -> &str {
+    match self {
+      &CortexError::Postgres(ref e) => e.description(),
+      &CortexError::Zmq(_) => "message queue error",
+      &CortexError::Io(ref e) => e.description(),
+      &CortexError::Protocol(ref message) => message,
+      &CortexError::AlreadyExists(_) => "item already exists",
+      &CortexError::Invalid(ref message) => message
+    }
+  }
+  fn cause(&self) -> Option<&error::Error> {
+    match self {
+      &CortexError::Postgres(ref e) => Some(e),
+      &CortexError::Io(ref e) => Some(e),
+      _ => None
+    }
+  }
+}
+
+impl From<postgres::error::Error> for CortexError {
+  fn from(e : postgres::error::Error) -> CortexError { CortexError::Postgres(e) }
+}
+impl From<zmq::Error> for CortexError {
+  fn from(e : zmq::Error) -> CortexError { CortexError::Zmq(e) }
+}
+impl From<io::Error> for CortexError {
+  fn from(e : io::Error) -> CortexError { CortexError::Io(e) }
+}
@@ -17,8 +17,8 @@ use std::path::Path;
 use std::path::PathBuf;
 use std::fs;
 use std::env;
-use std::io::Error;
 use backend::{Backend};
+use error::CortexError;
 use data::{Task, TaskStatus, Corpus};
 
 /// Struct for performing corpus imports into CorTeX
@@ -206,12 +206,45 @@ impl Importer {
     Ok(())
   }
   /// Given a CorTeX-topology corpus, walk the file system and import it into the Task store
-  pub fn walk_import<'walk>(&self) -> Result<(),Error> {
-    println!("-- Starting import walk");
-    let import_extension = if self.corpus.complex { "zip" } else { "tex" };
-    let mut walk_q : Vec<PathBuf> = vec![Path::new(&self.corpus.path).to_owned()];
+  pub fn walk_import<'walk>(&self) -> Result<(), CortexError> {
+    info!("Starting import walk");
     let mut import_q : Vec<Task> = Vec::new();
     let mut import_counter = 0;
+    try!(Importer::walk_entries(&self.corpus, |current_entry_path| {
+      // Found the expected file, import this entry:
+      debug!("Found entry: {:?}", current_entry_path);
+      import_counter += 1;
+      import_q.push(self.new_task(current_entry_path));
+      if import_q.len() >= 1000 {
+        // Flush the import queue to backend:
+        for skipped_entry in try!(self.backend.mark_imported(&import_q)) {
+          warn!("Skipped entry {:?}, which could not be imported.", skipped_entry);
+        }
+        import_q.clear();
+      }
+      Ok(())
+    }));
+    if !import_q.is_empty() {
+      for skipped_entry in try!(self.backend.mark_imported(&import_q)) {
+        warn!("Skipped entry {:?}, which could not be imported.", skipped_entry);
+      }
+    }
+    info!("Imported {} entries.", import_counter);
+    Ok(())
+  }
+
+  /// Walk the file system of a CorTeX-topology corpus, calling `on_entry` with the path of every entry found
+  pub fn walk_entries<F>(corpus : &Corpus, on_entry : F) -> Result<(), CortexError>
+    where F: FnMut(String) -> Result<(), CortexError> {
+    let import_extension = if corpus.complex { "zip" } else { "tex" };
+    Importer::walk_entries_with_extension(corpus, import_extension, on_entry)
+  }
+
+  /// Walk the file system of a CorTeX-topology corpus, calling `on_entry` with the path of every entry
+  /// with the given file `import_extension` (other files in the entry directories are skipped)
+  pub fn walk_entries_with_extension<F>(corpus : &Corpus, import_extension : &str, mut on_entry : F) -> Result<(), CortexError>
+    where F: FnMut(String) -> Result<(), CortexError> {
+    let mut walk_q : Vec<PathBuf> = vec![Path::new(&corpus.path).to_owned()];
     while walk_q.len() > 0 { 
       let current_path = walk_q.pop().unwrap();
       // println!("-- current path {:?}", current_path);
@@ -222,17 +255,7 @@ impl Importer {
         let current_entry = current_local_dir.to_str().unwrap().to_string() + "." + import_extension;
         let current_entry_path = current_path.to_str().unwrap().to_string() + "/" + &current_entry;
         match fs::metadata(current_entry_path.clone()) {
-          Ok(_) => {
-            // Found the expected file, import this entry:
-            println!("Found entry: {:?}", current_entry_path);
-            import_counter += 1;
-            import_q.push(self.new_task(current_entry_path));
-            if import_q.len() >= 1000 {
-              // Flush the import queue to backend:
-              self.backend.mark_imported(&import_q).unwrap(); // TODO: Proper Error-handling 
-              import_q.clear();
-            }
-          },
+          Ok(_) => try!(on_entry(current_entry_path)),
           Err(_) => {
             //  No such entry found, traversing into the directory:
             for subentry in try!(fs::read_dir(current_path.clone())) {
@@ -243,24 +266,35 @@ impl Importer {
         }
       }
     }
-    if !import_q.is_empty() {
-      self.backend.mark_imported(&import_q).unwrap();} // TODO: Proper Error-handling
-    println!("--- Imported {:?} entries.", import_counter);
     Ok(())
   }
 
   /// Create a new NoProblem task for the "import" service and the Importer-specified corpus
   pub fn new_task(&self, entry : String) -> Task {
-    let abs_entry : String = if Path::new(&entry).is_relative() {
-      let mut new_abs = self.cwd.clone();
+    let abs_entry = Importer::absolute_entry(&self.cwd, entry);
+    Task {id: None, entry : abs_entry, status : TaskStatus::NoProblem.raw(), corpusid : self.corpus.id.unwrap(), serviceid: 2, metadata : None}
+  }
+
+  /// Resolve an entry path against a working directory, unless it is already absolute
+  pub fn absolute_entry(cwd : &Path, entry : String) -> String {
+    if Path::new(&entry).is_relative() {
+      let mut new_abs = cwd.to_path_buf();
       new_abs.push(&entry);
       new_abs.to_str().unwrap().to_string()
     } else {
-      entry.clone()
-    };
-    
-    Task {id: None, entry : abs_entry, status : TaskStatus::NoProblem.raw(), corpusid : self.corpus.id.unwrap(), serviceid: 2}
+      entry
+    }
   }
+
+  /// Express an entry path relative to a corpus root, unless it lies outside of it
+  pub fn relative_entry(root : &Path, entry : String) -> String {
+    let relative = Path::new(&entry).strip_prefix(root).ok().map(|relative| relative.to_str().unwrap().to_string());
+    match relative {
+      Some(relative) => relative,
+      None => entry
+    }
+  }
+
   /// Top-level import driver, performs an optional unpack, and then an import into the Task store
   pub fn process(&self) -> Result<(),()> {
     // println!("Greetings from the import processor");
@@ -24,8 +24,11 @@ extern crate rand;
 extern crate tempfile;
 extern crate pericortex;
 extern crate time;
+#[macro_use]
+extern crate log;
 
 pub mod backend;
+pub mod error;
 pub mod importer;
 pub mod data;
 pub mod sysinfo;
@@ -10,25 +10,35 @@
 extern crate zmq;
 extern crate tempfile;
 
-use zmq::{Error, SNDMORE};
+use zmq::SNDMORE;
 use backend::{Backend, DEFAULT_DB_ADDRESS};
+use error::CortexError;
 use data::{TaskReport, TaskStatus, TaskProgress, TaskMessage, Service};
+use rustc_serialize::json::{Json, ToJson};
 
 use std::thread;
 use std::time::Duration;
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 use std::ops::Deref;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use std::path::Path;
+use std::fs;
 use std::fs::File;
 // use tempfile::TempFile;
 use std::io::{Write};
 use std::io::Read;
 
 use time;
+/// Attempts at persisting a batch of reports, before the acknowledging sink tells its worker to retry,
+/// or the finalize thread requeues the batch
+const ACK_PERSIST_ATTEMPTS : u32 = 5;
+/// Pause before the first retry of a failed persist, doubled for every further retry
+const ACK_PERSIST_BACKOFF_MS : u64 = 100;
+
 /// Manager struct responsible for dispatching and receiving tasks
 pub struct TaskManager {
   /// port for requesting/dispatching jobs
@@ -42,7 +52,20 @@ pub struct TaskManager {
   /// (keep this small to avoid large RAM use, increase to reduce network bandwidth)
   pub message_size : usize,
   /// address for the Task store postgres endpoint
-  pub backend_address : String
+  pub backend_address : String,
+  /// seconds between two maintenance passes of the supervisor
+  pub maintenance_interval : u64,
+  /// seconds after which a claimed task that was never completed is requeued
+  pub task_timeout : i64,
+  /// should the dispatched tasks carry the bytes of their entry, rather than just its path?
+  pub send_payload : bool,
+  /// should workers send their results via REQ sockets, and wait for the sink to acknowledge them once persisted?
+  pub acknowledge_results : bool,
+  /// lint mode: only keep the reports (status and logs) of the results, deleting their converted artifacts
+  /// (saves disk space during large validation sweeps)
+  pub discard_output : bool,
+  /// dispatch counters of the ventilator, shared with both servers for monitoring
+  pub ventilator_stats : Arc<VentilatorStats>
 }
 /// Represents a message queue server, can be used as either dispatcher or a sink
 pub struct Server {
@@ -57,7 +80,37 @@ pub struct Server {
   /// a backend struct, in order to reuse the same connection 
   pub backend : Backend,
   /// address for the Task store postgres endpoint
-  pub backend_address : String
+  pub backend_address : String,
+  /// should the dispatched tasks carry the bytes of their entry, rather than just its path?
+  /// (only workers with access to the corpus file system can run without a payload)
+  pub send_payload : bool,
+  /// should the sink reply to each result (REQ/REP), once it is persisted, rather than silently pull it (PUSH/PULL)?
+  pub acknowledge_results : bool,
+  /// dispatch counters of the ventilator, shared for monitoring
+  pub ventilator_stats : Arc<VentilatorStats>,
+  /// should the sink delete each result archive once its report is generated?
+  pub discard_output : bool
+}
+
+#[derive(Default)]
+/// Counters maintained by a running ventilator, e.g. for reporting its throughput
+pub struct VentilatorStats {
+  /// number of tasks dispatched to workers
+  pub dispatched : AtomicUsize,
+  /// number of requests for which no task was available
+  pub empty_responses : AtomicUsize,
+  /// number of requests which could not be served (e.g. unknown services or unreadable entries)
+  pub errors : AtomicUsize
+}
+
+/// Performs periodic maintenance of the Task store and reports on the health of a running manager
+pub struct Supervisor {
+  /// seconds between two maintenance passes
+  pub interval : u64,
+  /// seconds after which a claimed task that was never completed is requeued
+  pub task_timeout : i64,
+  /// a backend struct, in order to reuse the same connection
+  pub backend : Backend
 }
 
 impl Default for TaskManager {
@@ -67,12 +120,27 @@ impl Default for TaskManager {
         result_port : 5555,
         queue_size : 100,
         message_size : 100000,
-        backend_address : DEFAULT_DB_ADDRESS.clone().to_string() 
+        backend_address : DEFAULT_DB_ADDRESS.clone().to_string(),
+        maintenance_interval : 60,
+        task_timeout : 3600,
+        send_payload : true,
+        acknowledge_results : false,
+        discard_output : false,
+        ventilator_stats : Arc::new(VentilatorStats::default())
     } } }
 
 impl TaskManager {
+  /// The dispatch counters of this manager's ventilator, which can be read while the manager is running
+  pub fn ventilator_stats(&self) -> Arc<VentilatorStats> {
+    self.ventilator_stats.clone()
+  }
   /// Starts a new manager, spinning of dispatch/sink servers, listening on the specified ports
-  pub fn start<'manager>(&'manager self, job_limit: Option<usize>) -> Result<(), Error> {
+  pub fn start<'manager>(&'manager self, job_limit: Option<usize>) -> Result<(), CortexError> {
+    // Refuse to dispatch anything through a pipeline whose formats don't line up
+    if let Err(e) = Backend::from_address(&self.backend_address).validate_pipeline() {
+      error!("Manager: {:?}", e);
+      return Err(e);
+    }
     // We'll use some local memoization shared between source and sink:
     let services: HashMap<String, Option<Service>> = HashMap::new();
     let progress_queue: HashMap<i64, TaskProgress> = HashMap::new();
@@ -87,6 +155,9 @@ impl TaskManager {
     let source_queue_size = self.queue_size.clone();
     let source_message_size = self.message_size.clone();
     let source_backend_address = self.backend_address.clone();
+    let source_send_payload = self.send_payload.clone();
+    let ventilator_stats_arc = self.ventilator_stats();
+    let source_ventilator_stats_arc = ventilator_stats_arc.clone();
 
     let vent_services_arc = services_arc.clone();
     let vent_progress_queue_arc = progress_queue_arc.clone();
@@ -97,7 +168,11 @@ impl TaskManager {
         queue_size : source_queue_size,
         message_size : source_message_size,
         backend : Backend::from_address(&source_backend_address),
-        backend_address : source_backend_address.clone()
+        backend_address : source_backend_address.clone(),
+        send_payload : source_send_payload,
+        acknowledge_results : false,
+        ventilator_stats : source_ventilator_stats_arc,
+        discard_output : false
       };
       sources.start_ventilator(vent_services_arc, vent_progress_queue_arc, vent_done_queue_arc, job_limit).unwrap();
     });
@@ -105,12 +180,14 @@ impl TaskManager {
     // Next prepare the finalize thread which will persist finished jobs to the DB
     let finalize_backend_address = self.backend_address.clone();
     let finalize_done_queue_arc = done_queue_arc.clone();
+    let sink_finished_arc = Arc::new(AtomicBool::new(false));
+    let finalize_sink_finished_arc = sink_finished_arc.clone();
     let finalize_thread = thread::spawn(move || {
-      let finalize_backend = Backend::from_address(&finalize_backend_address);
+      let mut finalize_backend = Backend::from_address(&finalize_backend_address);
       let mut finalize_jobs_count : usize = 0;
       // Persist every 1 second, if there is something to record
       'markdonejob: loop {
-        match Server::mark_done_arc(&finalize_backend, &finalize_done_queue_arc) {
+        match Server::mark_done_arc(&mut finalize_backend, &finalize_done_queue_arc) {
           true => {
             finalize_jobs_count+=1;
             true;
@@ -122,6 +199,10 @@ impl TaskManager {
         if job_limit.is_some() && (finalize_jobs_count >= job_limit.unwrap()) {
           break
         }
+        // An acknowledging sink persists its own reports, so the job count alone may never be reached
+        if finalize_sink_finished_arc.load(Ordering::SeqCst) && finalize_done_queue_arc.lock().unwrap().is_empty() {
+          break
+        }
       };
     });
 
@@ -130,6 +211,9 @@ impl TaskManager {
     let result_queue_size = self.queue_size.clone();
     let result_message_size = self.message_size.clone();
     let result_backend_address = self.backend_address.clone();
+    let result_acknowledge_results = self.acknowledge_results.clone();
+    let result_discard_output = self.discard_output.clone();
+    let result_ventilator_stats_arc = ventilator_stats_arc.clone();
 
     let sink_services_arc = services_arc.clone();
     let sink_progress_queue_arc = progress_queue_arc.clone();
@@ -141,41 +225,114 @@ impl TaskManager {
         queue_size : result_queue_size,
         message_size : result_message_size,
         backend : Backend::from_address(&result_backend_address),
-        backend_address: result_backend_address.clone()
+        backend_address: result_backend_address.clone(),
+        send_payload : false,
+        acknowledge_results : result_acknowledge_results,
+        ventilator_stats : result_ventilator_stats_arc,
+        discard_output : result_discard_output
+      };
+      let sink_result = results.start_sink(sink_services_arc, sink_progress_queue_arc, sink_done_queue_arc, job_limit);
+      sink_finished_arc.store(true, Ordering::SeqCst);
+      sink_result.unwrap();
+    });
+
+    // Last, the supervisor performs periodic maintenance until the other threads are done
+    let supervisor_backend_address = self.backend_address.clone();
+    let supervisor_interval = self.maintenance_interval.clone();
+    let supervisor_task_timeout = self.task_timeout.clone();
+    let supervisor_progress_queue_arc = progress_queue_arc.clone();
+    let supervisor_done_queue_arc = done_queue_arc.clone();
+    let shutdown_arc = Arc::new(AtomicBool::new(false));
+    let supervisor_shutdown_arc = shutdown_arc.clone();
+    let supervisor_thread = thread::spawn(move || {
+      let supervisor = Supervisor {
+        interval : supervisor_interval,
+        task_timeout : supervisor_task_timeout,
+        backend : Backend::from_address(&supervisor_backend_address)
       };
-      results.start_sink(sink_services_arc, sink_progress_queue_arc, sink_done_queue_arc, job_limit).unwrap();
+      supervisor.start(supervisor_progress_queue_arc, supervisor_done_queue_arc, supervisor_shutdown_arc).unwrap();
     });
 
-    if vent_thread.join().is_err() {
-      println!("Ventilator thread died unexpectedly!");
-      Err(zmq::Error::ETERM)
+    let vent_joined = vent_thread.join();
+    let sink_joined = sink_thread.join();
+    let finalize_joined = finalize_thread.join();
+    shutdown_arc.store(true, Ordering::SeqCst);
+    let supervisor_joined = supervisor_thread.join();
+
+    if vent_joined.is_err() {
+      error!("Ventilator thread died unexpectedly!");
+      Err(CortexError::Zmq(zmq::Error::ETERM))
+    }
+    else if sink_joined.is_err() {
+      error!("Sink thread died unexpectedly!");
+      Err(CortexError::Zmq(zmq::Error::ETERM))
     }
-    else if sink_thread.join().is_err() {
-      println!("Sink thread died unexpectedly!");
-      Err(zmq::Error::ETERM)
+    else if supervisor_joined.is_err() {
+      error!("Supervisor thread died unexpectedly!");
+      Err(CortexError::Zmq(zmq::Error::ETERM))
     }
-    else if finalize_thread.join().is_err() {
-      println!("DB thread died unexpectedly!");
-      Err(zmq::Error::ETERM)
+    else if finalize_joined.is_err() {
+      error!("DB thread died unexpectedly!");
+      Err(CortexError::Zmq(zmq::Error::ETERM))
     }
     else {
-      println!("Manager successfully terminated!");
+      info!("Ventilator dispatched {} tasks, with {} empty responses and {} errors.",
+        ventilator_stats_arc.dispatched.load(Ordering::SeqCst),
+        ventilator_stats_arc.empty_responses.load(Ordering::SeqCst),
+        ventilator_stats_arc.errors.load(Ordering::SeqCst));
+      info!("Manager successfully terminated!");
       Ok(())
     }
   }
 }
 
+impl Supervisor {
+  /// Starts the maintenance loop, which requeues expired tasks in the Task store (dropping them from the
+  /// progress queue), and reports
+  /// the sizes of the shared queues, every `interval` seconds until the `shutdown` flag is raised.
+  pub fn start(&self,
+      progress_queue_arc : Arc<Mutex<HashMap<i64, TaskProgress>>>,
+      done_queue_arc : Arc<Mutex<Vec<TaskReport>>>,
+      shutdown : Arc<AtomicBool>)
+      -> Result<(), CortexError> {
+    'supervisejob: loop {
+      let requeued = try!(self.backend.requeue_expired_tasks(self.task_timeout));
+      // The requeued tasks will be dispatched anew, so they are no longer tracked as in progress
+      let in_progress = {
+        let mut progress_queue = progress_queue_arc.lock().unwrap();
+        for taskid in requeued.iter() {
+          progress_queue.remove(taskid);
+        }
+        progress_queue.len()
+      };
+      let pending_reports = done_queue_arc.lock().unwrap().len();
+      info!("Supervisor: {} tasks in progress, {} reports pending, {} expired tasks requeued.",
+        in_progress, pending_reports, requeued.len());
+      // Sleep until the next pass, checking for the shutdown signal every second
+      for _ in 0..self.interval.max(1) {
+        if shutdown.load(Ordering::SeqCst) {
+          break 'supervisejob;
+        }
+        thread::sleep(Duration::new(1,0));
+      }
+    }
+    Ok(())
+  }
+}
+
 impl Server {
   /// Starts a new dispatch `Server` (ZMQ Ventilator), to serve tasks to processing workers.
   /// The ventilator shares state with other manager threads via queues for tasks in progress, 
   /// as well as a queue for completed tasks pending persisting to disk.
+  /// Each request names the service the worker wants tasks for, and is only served tasks of that service;
+  /// requests for an unknown service are answered with an "error" frame, followed by a description frame.
   /// A job limit can be provided as a termination condition for the sink server.
   pub fn start_ventilator(&self, 
       services_arc : Arc<Mutex<HashMap<String, Option<Service>>>>,
       progress_queue_arc : Arc<Mutex<HashMap<i64, TaskProgress>>>,
       done_queue_arc : Arc<Mutex<Vec<TaskReport>>>,
       job_limit : Option<usize>)
-      -> Result <(),Error> {
+      -> Result<(), CortexError> {
     // We have a Ventilator-exclusive "queues" stack for tasks to be dispatched
     let mut queues : HashMap<String, Vec<TaskProgress>> = HashMap::new();
     // Assuming this is the only And tidy up the postgres tasks:
@@ -186,6 +343,7 @@ impl Server {
     let port_str = self.port.to_string();
     let address = "tcp://*:".to_string() + &port_str;
     assert!(ventilator.bind(&address).is_ok());
+    info!("Ventilator bound to {}", address);
     let mut source_job_count : usize = 0;
 
     'ventjob: loop {
@@ -193,27 +351,55 @@ impl Server {
       let mut identity = zmq::Message::new().unwrap();
       ventilator.recv(&mut identity, 0).unwrap();
       ventilator.recv(&mut msg, 0).unwrap();
-      let service_name = msg.as_str().unwrap().to_string();
+      let worker_id = identity.as_str().map(|worker_id| worker_id.to_string());
+      let service_name = match msg.as_str() {
+        Some(some_name) => some_name.to_string(),
+        None => "_unknown_".to_string()
+      };
       // println!("Task requested for service: {}", service_name.clone());
+      // Batch mode: the service name may be followed by a frame with the desired number of tasks
+      let batch_size : Option<usize> = if ventilator.get_rcvmore().unwrap() {
+        let mut batch_msg = zmq::Message::new().unwrap();
+        ventilator.recv(&mut batch_msg, 0).unwrap();
+        batch_msg.as_str().and_then(|size_str| size_str.parse::<usize>().ok())
+      } else {
+        None
+      };
       let request_time = time::get_time();
       source_job_count += 1;
 
-      let mut dispatched_task : Option<TaskProgress> = None;
+      let mut dispatched_tasks : Vec<TaskProgress> = Vec::new();
       match self.get_sync_service_record(&services_arc, service_name.clone()) {
-        None => {},
+        None => {
+          self.ventilator_stats.errors.fetch_add(1, Ordering::SeqCst);
+          // Answer, so that the worker isn't left waiting for tasks that will never come
+          warn!("Source job {}, requested unknown service {:?}.", source_job_count, service_name);
+          ventilator.send_msg(identity, SNDMORE).unwrap();
+          ventilator.send_str("error", SNDMORE).unwrap();
+          ventilator.send_str(&format!("unknown service {}", service_name), 0).unwrap();
+        },
         Some(service) => {
           if !queues.contains_key(&service_name) {
             queues.insert(service_name.clone(), Vec::new()); 
           }
           let mut task_queue : &mut Vec<TaskProgress> = queues.get_mut(&service_name).unwrap();
-          if task_queue.is_empty() {
+          if task_queue.len() < batch_size.unwrap_or(1) {
             // Refetch a new batch of tasks
             let now = time::get_time().sec;
-            task_queue.extend(self.backend.fetch_tasks(&service, self.queue_size).unwrap()
-              .into_iter().map(|task| TaskProgress {
+            // Tasks requeued (e.g. by the supervisor) while still waiting here or in progress are already held,
+            // refetching them must not dispatch them twice
+            let held_taskids : HashSet<i64> = {
+              let progress_queue = progress_queue_arc.lock().unwrap();
+              task_queue.iter().filter_map(|task_progress| task_progress.task.id)
+                .chain(progress_queue.keys().cloned()).collect()
+            };
+            task_queue.extend(self.backend.fetch_tasks(&service, self.queue_size, false, None).unwrap()
+              .into_iter().filter(|task| !held_taskids.contains(&task.id.unwrap())).map(|task| TaskProgress {
                 task: task,
                 created_at : now,
-                retries : 0
+                retries : 0,
+                worker_id : None,
+                dispatched_at_ms : None
               })); 
 
             // This is a good time to also take care that none of the old tasks are dead in the progress queue
@@ -229,73 +415,111 @@ impl Server {
                     severity : "fatal".to_string(), 
                     what : "never_completed_with_retries".to_string(), 
                     details : String::new()
-                  }]
+                  }],
+                  duration_ms : None,
+                  worker_id : expired_t.worker_id.clone(),
+                  artifact_formats : Vec::new()
                 });
               } else { // We can still retry, re-add to the dispatch queue
                 task_queue.push(TaskProgress {
                   task : expired_t.task,
                   created_at : expired_t.created_at,
-                  retries : expired_t.retries + 1
+                  retries : expired_t.retries + 1,
+                  worker_id : None,
+                  dispatched_at_ms : None
                 });
               }
             }
           }
-          match task_queue.pop() {
-            Some(current_task_progress) => {
-              dispatched_task = Some(current_task_progress.clone());
-
-              let current_task = current_task_progress.task;
-              let taskid = current_task.id.unwrap();
-              let serviceid = current_task.serviceid;
-
+          match batch_size {
+            Some(batch_size) => {
+              // Reply with a single frame, holding a JSON array of up to batch_size tasks
+              let batch_start = task_queue.len().saturating_sub(batch_size);
+              let batch = task_queue.split_off(batch_start);
+              let batch_json = Json::Array(batch.iter().map(|task_progress| task_progress.task.to_json()).collect());
               ventilator.send_msg(identity, SNDMORE).unwrap();
-              ventilator.send_str(&taskid.to_string(), SNDMORE).unwrap();
-              if serviceid == 1 { // No payload needed for init
-                ventilator.send(&[],0).unwrap(); }
-              else {
-                // Regular services fetch the task payload and transfer it to the worker
-                let file_opt = current_task.prepare_input_stream();
-                if file_opt.is_ok() {
-                  let mut file = file_opt.unwrap();        
-                  let mut total_outgoing : usize = 0;
-                  'streaminputjob: loop {
-                    // Stream input data via zmq
-                    let mut data = vec![0; self.message_size];
-                    let size = file.read(&mut data).unwrap();
-                    total_outgoing += size;
-                    data.truncate(size);
-                    
-                    if size < self.message_size {
-                      // If exhausted, send the last frame
-                      ventilator.send(&data,0).unwrap(); 
-                      // And terminate
-                      break
+              ventilator.send_str(&batch_json.to_string(), 0).unwrap();
+              info!("Source job {}, dispatched a batch of {} tasks.", source_job_count, batch.len());
+              if batch.is_empty() {
+                self.ventilator_stats.empty_responses.fetch_add(1, Ordering::SeqCst);
+              } else {
+                self.ventilator_stats.dispatched.fetch_add(batch.len(), Ordering::SeqCst);
+              }
+              dispatched_tasks.extend(batch.into_iter());
+            },
+            None => {
+              match task_queue.pop() {
+                Some(current_task_progress) => {
+                  dispatched_tasks.push(current_task_progress.clone());
+                  self.ventilator_stats.dispatched.fetch_add(1, Ordering::SeqCst);
+
+                  let current_task = current_task_progress.task;
+                  let taskid = current_task.id.unwrap();
+                  let serviceid = current_task.serviceid;
+
+                  ventilator.send_msg(identity, SNDMORE).unwrap();
+                  ventilator.send_str(&taskid.to_string(), SNDMORE).unwrap();
+                  if serviceid == 1 { // No payload needed for init
+                    ventilator.send(&[],0).unwrap(); }
+                  else if !self.send_payload {
+                    // Workers with access to the corpus file system only need the entry path
+                    ventilator.send_str(&current_task.entry, 0).unwrap(); }
+                  else {
+                    // Regular services fetch the task payload and transfer it to the worker
+                    let file_opt = current_task.prepare_input_stream();
+                    if file_opt.is_ok() {
+                      let mut file = file_opt.unwrap();        
+                      let mut total_outgoing : usize = 0;
+                      'streaminputjob: loop {
+                        // Stream input data via zmq
+                        let mut data = vec![0; self.message_size];
+                        let size = file.read(&mut data).unwrap();
+                        total_outgoing += size;
+                        data.truncate(size);
+                        
+                        if size < self.message_size {
+                          // If exhausted, send the last frame
+                          ventilator.send(&data,0).unwrap(); 
+                          // And terminate
+                          break
+                        } else {
+                          // If more to go, send the frame and indicate there's more to come
+                          ventilator.send(&data,SNDMORE).unwrap();
+                        }
+                      }
+                      let responded_time = time::get_time();
+                      let request_duration = (responded_time - request_time).num_milliseconds();
+                      info!("Source job {}, message size: {}, took {}ms.", source_job_count, total_outgoing, request_duration);
                     } else {
-                      // If more to go, send the frame and indicate there's more to come
-                      ventilator.send(&data,SNDMORE).unwrap();
+                      // TODO: smart handling of failures
+                      self.ventilator_stats.errors.fetch_add(1, Ordering::SeqCst);
+                      ventilator.send(&[],0).unwrap(); 
                     }
                   }
-                  let responded_time = time::get_time();
-                  let request_duration = (responded_time - request_time).num_milliseconds();
-                  println!("Source job {}, message size: {}, took {}ms.", source_job_count, total_outgoing, request_duration);
-                } else {
-                  // TODO: smart handling of failures
-                  ventilator.send(&[],0).unwrap(); 
+                },
+                None => {
+                  self.ventilator_stats.empty_responses.fetch_add(1, Ordering::SeqCst);
                 }
-              }
-            },
-            None => {}
+              };
+            }
           };
         }
       };
-      // Record that a task has been dispatched in the progress queue
-      if dispatched_task.is_some() {
-        Server::push_progress_task(&progress_queue_arc, dispatched_task.unwrap());
+      // Record that the tasks have been dispatched in the progress queue
+      // (stamped with the request time, so that their durations span from dispatch to result)
+      let dispatched_at_ms = request_time.sec * 1000 + (request_time.nsec / 1000000) as i64;
+      for mut dispatched_task in dispatched_tasks.into_iter() {
+        dispatched_task.worker_id = worker_id.clone();
+        dispatched_task.dispatched_at_ms = Some(dispatched_at_ms);
+        Server::push_progress_task(&progress_queue_arc, dispatched_task);
       }
       if job_limit.is_some() && (source_job_count >= job_limit.unwrap()) {
         break
       }
     }
+    // Close the socket before its context, so that the context can terminate cleanly
+    try!(ventilator.close());
+    try!(context.destroy());
     Ok(())
   }
   /// Starts a receiver/sink `Server` (ZMQ Pull), to accept processing responses.
@@ -307,14 +531,16 @@ impl Server {
       progress_queue_arc : Arc<Mutex<HashMap<i64, TaskProgress>>>,
       done_queue_arc : Arc<Mutex<Vec<TaskReport>>>,
       job_limit: Option<usize>)
-      -> Result <(),Error> {
+      -> Result<(), CortexError> {
 
     // Ok, let's bind to a port and start broadcasting
     let mut context = zmq::Context::new();
-    let mut sink = context.socket(zmq::PULL).unwrap();
+    let sink_type = if self.acknowledge_results { zmq::REP } else { zmq::PULL };
+    let mut sink = context.socket(sink_type).unwrap();
     let port_str = self.port.to_string();
     let address = "tcp://*:".to_string() + &port_str;
     assert!(sink.bind(&address).is_ok());
+    info!("Sink bound to {}", address);
 
     let mut sink_job_count : usize = 0;
 
@@ -341,17 +567,23 @@ impl Server {
       // We have a job, count it
       sink_job_count += 1;
       let mut total_incoming = 0;
+      // The reports of this job, persisted right away when acknowledging, or handed to the finalize thread otherwise
+      let mut job_reports : Vec<TaskReport> = Vec::new();
       let request_time = time::get_time();
-      println!("Incoming sink job {:?} for Service: {:?}, taskid: {:?}", sink_job_count, service_name, taskid_str);
+      info!("Incoming sink job {:?} for Service: {:?}, taskid: {:?}", sink_job_count, service_name, taskid_str);
 
       match Server::pop_progress_task(&progress_queue_arc, taskid) {
-        None => {}, // TODO: No such task, what to do?
+        None => { // TODO: No such task, what to do?
+          warn!("Sink job {} has a result for taskid {}, which is not in progress.", sink_job_count, taskid_str);
+        },
         Some(task_progress) => {
+          let duration_ms = task_progress.dispatch_duration_ms();
+          let worker_id = task_progress.worker_id;
           let task = task_progress.task;
           let service_option = Server::get_service_record(&services_arc, service_name.to_string());
           match service_option.clone() {
             None => {
-              println!("Error TODO: Server::get_service_record found nothing.");
+              error!("Error TODO: Server::get_service_record found nothing for service {:?}, taskid {}.", service_name, taskid);
             }, // TODO: Handle errors
             Some(service) => {
               let serviceid = match service.id {
@@ -365,26 +597,29 @@ impl Server {
                   match sink.recv(&mut recv_msg, 0) {
                     Ok(_) => {},
                     Err(e) => {
-                      println!("Error TODO: sink.recv failed: {:?}",e);
+                      error!("Error TODO: sink.recv failed: {:?}",e);
                     }
                   };
                   let done_report = TaskReport {
                     task : task.clone(),
                     status : TaskStatus::NoProblem,
-                    messages : Vec::new()
+                    messages : Vec::new(),
+                    duration_ms : duration_ms,
+                    worker_id : worker_id.clone(),
+                    artifact_formats : Vec::new()
                   };
-                  Server::push_done_queue(&done_queue_arc, done_report);
+                  job_reports.push(done_report);
                 }
                 else {                
                   // Receive the rest of the input in the correct file
                   match Path::new(&task.entry).parent() {
                     None => {
-                      println!("Error TODO: Path::new(&task.entry).parent() failed.");
+                      error!("Error TODO: Path::new(&task.entry).parent() failed.");
                     },
                     Some(recv_dir) => {
                       match recv_dir.to_str() {
                         None => {
-                          println!("Error TODO: recv_dir.to_str() failed");
+                          error!("Error TODO: recv_dir.to_str() failed");
                         },
                         Some(recv_dir_str) => {
                           let recv_dir_string = recv_dir_str.to_string();
@@ -395,7 +630,10 @@ impl Server {
                             let mut file = match File::create(recv_path) {
                               Ok(f) => f,
                               Err(e) => {
-                                println!("Error TODO: File::create(recv_path): {:?}", e);
+                                error!("Error TODO: File::create(recv_path): {:?}", e);
+                                if self.acknowledge_results {
+                                  try!(self.acknowledge(&mut sink, &done_queue_arc, Vec::new()));
+                                }
                                 continue;
                               }
                             };
@@ -403,14 +641,14 @@ impl Server {
                               match sink.recv(&mut recv_msg, 0) {
                                 Ok(_) => {},
                                 Err(e) => {
-                                  println!("Error TODO: sink.recv (line 309) failed: {:?}",e);
+                                  error!("Error TODO: sink.recv (line 309) failed: {:?}",e);
                                 }
                               };
 
                               match file.write(recv_msg.deref()) {
                                 Ok(written_bytes) => { total_incoming += written_bytes },
                                 Err(e) => { 
-                                  println!("Error TODO: file.write(recv_msg.deref()) failed: {:?}",e); 
+                                  error!("Error TODO: file.write(recv_msg.deref()) failed: {:?}",e); 
                                   break;
                                 }
                               };
@@ -418,7 +656,7 @@ impl Server {
                                 Ok(false) => break,
                                 Ok(true) => {},
                                 Err(e) => {
-                                  println!("Error TODO: sink.get_rcvmore failed: {:?}", e);
+                                  error!("Error TODO: sink.get_rcvmore failed: {:?}", e);
                                   break;
                                 }
                               };
@@ -426,8 +664,18 @@ impl Server {
                             drop(file);
                           }
                           // Then mark the task done. This can be in a new thread later on
-                          let done_report = task.generate_report(recv_path);
-                          Server::push_done_queue(&done_queue_arc, done_report);
+                          let mut done_report = task.generate_report(recv_path);
+                          // Only the formats the service declares count as its artifacts (skipping e.g. images)
+                          let service_formats = service.output_formats();
+                          done_report.artifact_formats.retain(|format| service_formats.contains(format));
+                          if self.discard_output {
+                            if let Err(e) = fs::remove_file(recv_path) {
+                              warn!("Sink job {} could not discard its result {:?}: {:?}", sink_job_count, recv_path, e);
+                            }
+                          }
+                          done_report.duration_ms = duration_ms;
+                          done_report.worker_id = worker_id.clone();
+                          job_reports.push(done_report);
                         }
                       }
                     }
@@ -448,13 +696,54 @@ impl Server {
           };
         }
       }
+      if self.acknowledge_results {
+        try!(self.acknowledge(&mut sink, &done_queue_arc, job_reports));
+      } else {
+        for report in job_reports.into_iter() {
+          Server::push_done_queue(&done_queue_arc, report);
+        }
+      }
       let responded_time = time::get_time();
       let request_duration = (responded_time - request_time).num_milliseconds();
-      println!("Sink job {}, message size: {}, took {}ms.", sink_job_count, total_incoming, request_duration);
+      info!("Sink job {}, message size: {}, took {}ms.", sink_job_count, total_incoming, request_duration);
       if job_limit.is_some() && (sink_job_count >= job_limit.unwrap()) {
         break
       }
     }
+    // Close the socket before its context, so that the context can terminate cleanly
+    try!(sink.close());
+    try!(context.destroy());
+    Ok(())
+  }
+
+  /// Replies to a worker's result in the acknowledging sink mode: "ok" once its `reports` are persisted,
+  /// "discarded" when no report was expected, or "error" when the Task store kept failing (the worker should then retry).
+  /// The reports are written directly, rather than via the shared done queue, so that the reply reflects their own commit.
+  /// Failed writes (e.g. on a database hiccup) are reattempted with an exponential backoff before giving up,
+  /// and the reports are then left to the finalize thread.
+  fn acknowledge(&self, sink : &mut zmq::Socket, done_queue_arc : &Arc<Mutex<Vec<TaskReport>>>, reports : Vec<TaskReport>) -> Result<(), CortexError> {
+    // Drain any frames of a result which was not read, so that the reply socket can respond
+    let mut drain_msg = try!(zmq::Message::new());
+    while try!(sink.get_rcvmore()) {
+      try!(sink.recv(&mut drain_msg, 0));
+    }
+    let ack = if reports.is_empty() {
+      "discarded"
+    } else {
+      let persisted = Server::persist_with_backoff(|| self.backend.mark_done(&reports));
+      match persisted {
+        Ok(_) => "ok",
+        Err(e) => {
+          error!("Sink could not persist its reports, requeueing them: {:?}", e);
+          // Keep the reports buffered, for the next persisting pass
+          for report in reports.into_iter() {
+            Server::push_done_queue(done_queue_arc, report);
+          }
+          "error"
+        }
+      }
+    };
+    try!(sink.send_str(ack, 0));
     Ok(())
   }
 
@@ -473,16 +762,49 @@ impl Server {
       Some(service_option) => service_option.clone()
     }
   }
-  /// Persists a shared vector of reports to the Task store
-  pub fn mark_done_arc(backend : &Backend, reports_arc: &Arc<Mutex<Vec<TaskReport>>>) -> bool {
+  /// Runs `persist` until it succeeds, reattempting failed writes (e.g. on a database hiccup) with an exponential backoff,
+  /// and returns the error of the last attempt when all `ACK_PERSIST_ATTEMPTS` failed
+  fn persist_with_backoff<F>(mut persist : F) -> Result<(), CortexError>
+    where F : FnMut() -> Result<(), CortexError> {
+    let mut backoff = Duration::from_millis(ACK_PERSIST_BACKOFF_MS);
+    let mut attempt = 1;
+    loop {
+      match persist() {
+        Ok(_) => return Ok(()),
+        Err(e) => {
+          if attempt >= ACK_PERSIST_ATTEMPTS {
+            return Err(e)
+          }
+          warn!("Could not persist reports ({:?}), retry {} of {} in {:?}.", e, attempt, ACK_PERSIST_ATTEMPTS - 1, backoff);
+          thread::sleep(backoff);
+          backoff = backoff * 2;
+          attempt += 1;
+        }
+      }
+    }
+  }
+  /// Persists a shared vector of reports to the Task store, returning whether any were persisted.
+  /// Reports which could not be persisted after all retries are put back in the queue, for a later pass.
+  pub fn mark_done_arc(backend : &mut Backend, reports_arc: &Arc<Mutex<Vec<TaskReport>>>) -> bool {
     let reports = Server::fetch_shared_vec(reports_arc);
     if reports.len() > 0 {
       let request_time = time::get_time();
-      backend.mark_done(&reports).unwrap(); // TODO: error handling if DB fails
-      let responded_time = time::get_time();
-      let request_duration = (responded_time - request_time).num_milliseconds();
-      println!("Reporting done tasks to DB took {}ms.", request_duration);
-      true
+      // Survive a dropped Task store connection, e.g. on a database restart
+      let persisted = Server::persist_with_backoff(|| backend.with_reconnect(|backend| backend.mark_done(&reports)));
+      match persisted {
+        Ok(_) => {
+          let responded_time = time::get_time();
+          let request_duration = (responded_time - request_time).num_milliseconds();
+          info!("Reporting done tasks to DB took {}ms.", request_duration);
+          true
+        },
+        Err(e) => {
+          error!("Could not persist {} done tasks, requeueing them: {:?}", reports.len(), e);
+          let mut queued_reports = reports_arc.lock().unwrap();
+          queued_reports.extend(reports.into_iter());
+          false
+        }
+      }
     } else {
       false
     }
@@ -5,23 +5,204 @@
 // This file may not be copied, modified, or distributed
 // except according to those terms.
 
-//! Worker for performing corpus imports, when served as "init" tasks by the CorTeX dispatcher
+//! Workers run by CorTeX itself: corpus imports, served as "init" tasks by the CorTeX dispatcher, and external conversion commands
 
 extern crate pericortex;
 extern crate zmq;
 extern crate rand;
 
 use zmq::{Context, Message, SNDMORE, Error};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::fs::File;
+use std::io;
+use std::process::{Command, Output};
 use std::thread;
 use std::time::Duration;
 
 use backend::{DEFAULT_DB_ADDRESS, Backend};
-use data::{Task, Corpus};
+use data::{Task, Corpus, Service};
+use error::CortexError;
 use importer::Importer;
 use pericortex::worker::Worker;
 
+/// Retry policy for the external conversion commands run by workers.
+/// Transient failures (e.g. temporary file races, or a process killed on an OOM spike) are retried internally
+/// after a short `delay`, while deterministic failures are reported right away.
+pub struct CommandRetry {
+  /// number of additional attempts after a transient failure, before the failure is reported
+  pub max_internal_retries : usize,
+  /// exit codes signaling a transient failure (termination by a signal always counts as transient)
+  pub transient_exit_codes : Vec<i32>,
+  /// pause between attempts
+  pub delay : Duration
+}
+impl Default for CommandRetry {
+  fn default() -> CommandRetry {
+    CommandRetry {
+      max_internal_retries : 2,
+      // EX_TEMPFAIL, and the shell's report of a SIGKILL (e.g. by the OOM killer)
+      transient_exit_codes : vec![75, 137],
+      delay : Duration::from_secs(1)
+    }
+  }
+}
+impl CommandRetry {
+  /// Runs the command built by `build_command`, rebuilding and rerunning it on transient failures,
+  /// and returns the output of the last attempt
+  pub fn output<F>(&self, build_command : F) -> io::Result<Output>
+    where F : Fn() -> Command {
+    let mut attempt = 0;
+    loop {
+      let output = try!(build_command().output());
+      let transient = match output.status.code() {
+        Some(code) => self.transient_exit_codes.contains(&code),
+        None => true
+      };
+      if output.status.success() || !transient || attempt >= self.max_internal_retries {
+        return Ok(output)
+      }
+      attempt += 1;
+      warn!("Transient command failure ({}), retry {} of {}.", output.status, attempt, self.max_internal_retries);
+      thread::sleep(self.delay);
+    }
+  }
+}
+
+/// Two-stage conversion for services with an `inputconverter`: the converter service runs first,
+/// and its result is fed into the main conversion. Each stage's command is run under the `retry` policy.
+pub struct ConverterChain {
+  /// the resolved input converter service, if any
+  pub input_converter : Option<Service>,
+  /// retry policy for the commands of both stages
+  pub retry : CommandRetry
+}
+impl ConverterChain {
+  /// Resolves the `inputconverter` of a service against the Task store, picking its latest version.
+  /// Fails with `Invalid` if the converter is not a known service, and with `Postgres` if the Task store can't be read.
+  pub fn resolve(backend : &Backend, service : &Service) -> Result<ConverterChain, CortexError> {
+    let input_converter = match service.inputconverter {
+      None => None,
+      Some(ref converter_name) => {
+        let stmt = try!(backend.connection.prepare(
+          "SELECT serviceid,name,version,inputformat,outputformat,inputconverter,complex FROM services
+           WHERE name=$1 and deleted_at IS NULL ORDER BY version DESC LIMIT 1"));
+        let rows = try!(stmt.query(&[converter_name]));
+        if rows.len() == 0 {
+          return Err(CortexError::Invalid(
+            format!("input converter {} of service {} is not a known service", converter_name, service.name)))
+        }
+        Some(Service::from_row(rows.get(0)))
+      }
+    };
+    Ok(ConverterChain { input_converter : input_converter, retry : CommandRetry::default() })
+  }
+  /// The path the converter stage writes its result to, following the sink's "<service name>.zip" convention
+  pub fn converter_output(input : &Path, converter : &Service) -> PathBuf {
+    input.with_file_name(converter.name.clone() + ".zip")
+  }
+  /// Runs the converter stage on `input`, if there is one, then the main conversion on its result
+  /// (or on `input` directly). `converter_command` builds the converter's command from the converter service,
+  /// its input and its output path, while `main_command` builds the main conversion from its input.
+  /// A failed stage is reported as `Invalid`, naming the stage, and stops the chain.
+  pub fn output<C, M>(&self, input : &Path, converter_command : C, main_command : M) -> Result<Output, CortexError>
+    where C : Fn(&Service, &Path, &Path) -> Command, M : Fn(&Path) -> Command {
+    let main_input = match self.input_converter {
+      None => input.to_path_buf(),
+      Some(ref converter) => {
+        let converter_output = ConverterChain::converter_output(input, converter);
+        let output = try!(self.retry.output(|| converter_command(converter, input, &converter_output)));
+        if !output.status.success() {
+          return Err(CortexError::Invalid(format!("input converter {} failed on {:?}: {}", converter.name, input, output.status)))
+        }
+        converter_output
+      }
+    };
+    let output = try!(self.retry.output(|| main_command(&main_input)));
+    if !output.status.success() {
+      return Err(CortexError::Invalid(format!("main conversion failed on {:?}: {}", main_input, output.status)))
+    }
+    Ok(output)
+  }
+}
+
+/// `Worker` running external programs on the dispatched entries, for services without a dedicated worker.
+/// Services with an `inputconverter` first run the `converter_program`, their main `program` then converts its result.
+/// Both programs are called with their input and output paths as the last two arguments.
+pub struct CommandWorker {
+  /// name of the service
+  pub service : String,
+  /// version, as usual
+  pub version : f32,
+  /// message size, as usual
+  pub message_size : usize,
+  /// full URL (including port) to task source/dispatcher
+  pub source : String,
+  /// full URL (including port) to task sink/receiver
+  pub sink : String,
+  /// address to the Task store backend, for resolving the input converter of the service
+  pub backend_address : String,
+  /// the main conversion program
+  pub program : String,
+  /// leading arguments of the main conversion program
+  pub args : Vec<String>,
+  /// the program of the input converter stage, required if the service has an input converter
+  pub converter_program : Option<String>,
+  /// leading arguments of the input converter program
+  pub converter_args : Vec<String>,
+  /// number of additional attempts of a program after a transient failure (see `CommandRetry`)
+  pub max_internal_retries : usize
+}
+impl CommandWorker {
+  /// Resolves the `ConverterChain` of this worker's service, surfacing Task store failures
+  pub fn converter_chain(&self) -> Result<ConverterChain, CortexError> {
+    let backend = Backend::from_address(&self.backend_address);
+    match try!(Service::from_name(&backend.connection, self.service.clone())) {
+      None => Err(CortexError::Invalid(format!("service {} is not a known service", self.service))),
+      Some(service) => ConverterChain::resolve(&backend, &service)
+    }
+  }
+  /// Runs the conversion chain of this worker's service on `input`, returning the path of the main conversion's result
+  pub fn run_chain(&self, input : &Path) -> Result<PathBuf, CortexError> {
+    let mut chain = try!(self.converter_chain());
+    chain.retry.max_internal_retries = self.max_internal_retries;
+    let converter_program = match (&chain.input_converter, &self.converter_program) {
+      (&Some(ref converter), &None) => return Err(CortexError::Invalid(
+        format!("no converter program given for the input converter {} of service {}", converter.name, self.service))),
+      (_, &Some(ref converter_program)) => converter_program.clone(),
+      (&None, &None) => String::new()
+    };
+    let output_path = input.with_file_name(self.service.clone() + ".zip");
+    try!(chain.output(input,
+      |_, converter_input, converter_output| {
+        let mut command = Command::new(&converter_program);
+        command.args(&self.converter_args).arg(converter_input).arg(converter_output);
+        command
+      },
+      |main_input| {
+        let mut command = Command::new(&self.program);
+        command.args(&self.args).arg(main_input).arg(&output_path);
+        command
+      }));
+    Ok(output_path)
+  }
+}
+impl Worker for CommandWorker {
+  fn service(&self) -> String {self.service.clone()}
+  fn source(&self) -> String {self.source.clone()}
+  fn sink(&self) -> String {self.sink.clone()}
+  fn message_size(&self) -> usize {self.message_size.clone()}
+
+  fn convert(&self, path : &Path) -> Option<File> {
+    match self.run_chain(path) {
+      Ok(output_path) => File::open(output_path).ok(),
+      Err(e) => {
+        error!("Service {} failed to convert {:?}: {:?}", self.service, path, e);
+        None
+      }
+    }
+  }
+}
+
 /// `Worker` for initializing/importing a new corpus into CorTeX
 pub struct InitWorker {
   /// name of the service ("init")
@@ -107,7 +288,8 @@ impl Worker for InitWorker {
         entry: String::new(),
         corpusid : 0,
         serviceid : 0,
-        status : 0
+        status : 0,
+        metadata : None
       };
       let task = match backend.sync(&placeholder_task) {
         Ok(t) => t,
@@ -136,6 +318,11 @@ impl Worker for InitWorker {
         None => {}
       };
     }
+    // Close the sockets before their contexts, so that the contexts can terminate cleanly
+    try!(source.close());
+    try!(sink.close());
+    try!(context_source.destroy());
+    try!(context_sink.destroy());
     Ok(())
   }
 }
@@ -6,16 +6,2161 @@
 // except according to those terms.
 extern crate cortex;
 extern crate postgres;
+extern crate rustc_serialize;
 
 use cortex::backend::*;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::thread;
+use std::time::Duration;
+use cortex::error::CortexError;
+use cortex::data::{CortexORM, Corpus, DispatchedTask, Service, Task, TaskMessage, TaskReport, TaskStatus};
+use rustc_serialize::json;
+use rustc_serialize::json::Json;
 
-#[test]
-fn init_tables() {
+fn mock_backend() -> Backend {
   let backend = Backend::testdb();
-  assert!(backend.setup_task_tables().is_ok())
+  if backend.needs_init().unwrap() {
+    backend.setup_task_tables().unwrap();
+  }
+  backend
+}
+
+fn mock_corpus(backend : &Backend, name : &str) -> Corpus {
+  backend.add(
+    Corpus {
+      id : None,
+      name : name.to_string(),
+      path : "tests/data/".to_string(),
+      complex : true
+    }).unwrap()
+}
+
+fn mock_service(backend : &Backend, name : &str) -> Service {
+  backend.add(
+    Service {
+      id : None,
+      name : name.to_string(),
+      version : 0.1,
+      inputformat : "tex".to_string(),
+      outputformat : "html".to_string(),
+      inputconverter : Some("import".to_string()),
+      complex : true
+    }).unwrap()
+}
+
+fn mock_task(backend : &Backend, entry : &str, service : &Service, corpus : &Corpus, status : TaskStatus) -> Task {
+  backend.add(
+    Task {
+      id : None,
+      entry : entry.to_string(),
+      serviceid : service.id.unwrap(),
+      corpusid : corpus.id.unwrap(),
+      status : status.raw(),
+      metadata : None
+    }).unwrap()
+}
+
+fn mock_report(task : &Task, status : TaskStatus, messages : Vec<(&str, &str, &str)>) -> TaskReport {
+  TaskReport {
+    task : task.clone(),
+    status : status,
+    messages : messages.into_iter().map(|(severity, category, what)| TaskMessage {
+      severity : severity.to_string(),
+      category : category.to_string(),
+      what : what.to_string(),
+      details : String::new()
+    }).collect(),
+    duration_ms : None,
+    worker_id : None,
+    artifact_formats : Vec::new()
+  }
 }
 
 #[test]
 fn import_mock_task() {
   // backend = 
-}
\ No newline at end of file
+}
+
+#[test]
+fn pause_and_resume_service() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "pause test corpus");
+  let service = mock_service(&backend, "pause_test_service");
+  mock_task(&backend, "/pause/test/entry.zip", &service, &corpus, TaskStatus::TODO);
+
+  assert!(backend.set_service_enabled(&service, false).is_ok());
+  assert!(backend.fetch_tasks(&service, 10, false, None).unwrap().is_empty());
+
+  assert!(backend.set_service_enabled(&service, true).is_ok());
+  assert_eq!(backend.fetch_tasks(&service, 10, false, None).unwrap().len(), 1);
+}
+
+#[test]
+fn task_report_severity_rollup() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "rollup test corpus");
+  let service = mock_service(&backend, "rollup_test_service");
+  mock_task(&backend, "/rollup/test/ok.zip", &service, &corpus, TaskStatus::NoProblem);
+  mock_task(&backend, "/rollup/test/warning.zip", &service, &corpus, TaskStatus::Warning);
+
+  let report = backend.task_report(&corpus, &service, None, None, None);
+  let names = report.iter().map(|row| row.get("name").unwrap().clone()).collect::<Vec<_>>();
+  assert_eq!(names, vec!["fatal", "error", "warning", "no_problem", "total"]);
+  assert_eq!(report[3].get("tasks").unwrap(), "1");
+  assert_eq!(report[4].get("tasks").unwrap(), "2");
+}
+
+#[test]
+fn close_many_backends() {
+  for index in 0..50 {
+    let backend = Backend::testdb();
+    if index % 2 == 0 {
+      assert!(backend.close().is_ok());
+    } else {
+      drop(backend);
+    }
+  }
+  // Connection slots were released, so we can still connect
+  let backend = Backend::testdb();
+  assert!(backend.close().is_ok());
+}
+
+#[test]
+fn search_entries_across_corpora() {
+  let backend = mock_backend();
+  let import_service = Service::from_name(&backend.connection, "import".to_string()).unwrap().unwrap();
+  let first_corpus = mock_corpus(&backend, "search test corpus A");
+  let second_corpus = mock_corpus(&backend, "search test corpus B");
+  mock_task(&backend, "/search/a/9901.77001/9901.77001.zip", &import_service, &first_corpus, TaskStatus::NoProblem);
+  mock_task(&backend, "/search/b/9901.77002/9901.77002.zip", &import_service, &second_corpus, TaskStatus::NoProblem);
+
+  let hits = backend.search_entries("9901.7700", 10).unwrap();
+  assert_eq!(hits.len(), 2);
+  assert!(hits.iter().any(|&(ref corpus, _)| corpus.id == first_corpus.id));
+  assert!(hits.iter().any(|&(ref corpus, _)| corpus.id == second_corpus.id));
+  assert!(hits.iter().all(|&(_, ref task)| !task.entry.ends_with(' ')));
+}
+
+#[test]
+fn task_report_severity_ordering() {
+  let mut backend = mock_backend();
+  let corpus = mock_corpus(&backend, "ordering test corpus");
+  let service = mock_service(&backend, "ordering_test_service");
+  mock_task(&backend, "/ordering/test/ok.zip", &service, &corpus, TaskStatus::NoProblem);
+  mock_task(&backend, "/ordering/test/warning1.zip", &service, &corpus, TaskStatus::Warning);
+  mock_task(&backend, "/ordering/test/warning2.zip", &service, &corpus, TaskStatus::Warning);
+
+  let by_severity = backend.task_report(&corpus, &service, None, None, None);
+  let severity_names = by_severity.iter().map(|row| row.get("name").unwrap().clone()).collect::<Vec<_>>();
+  assert_eq!(severity_names, vec!["fatal", "error", "warning", "no_problem", "total"]);
+
+  backend.report_order = ReportOrder::ByCount;
+  let by_count = backend.task_report(&corpus, &service, None, None, None);
+  assert_eq!(by_count[0].get("name").unwrap(), "warning");
+  assert_eq!(by_count[1].get("name").unwrap(), "no_problem");
+  assert_eq!(by_count[4].get("name").unwrap(), "total");
+}
+
+#[test]
+fn status_partial_indexes() {
+  let backend = mock_backend();
+  let index_query = backend.connection.prepare("SELECT indexdef FROM pg_indexes WHERE tablename='tasks' and indexname=$1").unwrap();
+  for status in vec![TaskStatus::NoProblem, TaskStatus::Warning, TaskStatus::Error, TaskStatus::Fatal,
+    TaskStatus::TODO, TaskStatus::from_key("blocked")].iter() {
+    let index_name = status.to_key() + "_index";
+    let rows = index_query.query(&[&index_name]).unwrap();
+    assert_eq!(rows.len(), 1);
+    let index_definition : String = rows.get(0).get(0);
+    assert!(index_definition.contains(&status.raw().to_string()));
+  }
+}
+
+#[test]
+fn blocked_status_uses_partial_index() {
+  let backend = mock_backend();
+  // Discourage sequential scans, so that the plan only falls back to one when no index applies
+  backend.connection.execute("SET enable_seqscan = off", &[]).unwrap();
+  let explain_query = backend.connection.prepare(&format!("EXPLAIN SELECT taskid FROM tasks WHERE status = {} and serviceid = 1 and corpusid = 1",
+    TaskStatus::from_key("blocked").raw())).unwrap();
+  let plan = explain_query.query(&[]).unwrap().iter().map(|row| row.get::<_, String>(0)).collect::<Vec<_>>().join("\n");
+  backend.connection.execute("SET enable_seqscan = on", &[]).unwrap();
+  assert!(plan.contains("blocked_index"));
+  assert!(!plan.contains("Seq Scan"));
+}
+
+#[test]
+fn top_whats_across_corpora() {
+  let backend = mock_backend();
+  let service = mock_service(&backend, "top_whats_test_service");
+  let first_corpus = mock_corpus(&backend, "top whats test corpus A");
+  let second_corpus = mock_corpus(&backend, "top whats test corpus B");
+  let first_task = mock_task(&backend, "/top_whats/a/entry.zip", &service, &first_corpus, TaskStatus::TODO);
+  let second_task = mock_task(&backend, "/top_whats/b/entry.zip", &service, &second_corpus, TaskStatus::TODO);
+  backend.mark_done(&vec![
+    mock_report(&first_task, TaskStatus::Error, vec![("error", "undefined", "top_whats_macro")]),
+    mock_report(&second_task, TaskStatus::Error, vec![("error", "undefined", "top_whats_macro"), ("warning", "expected", "top_whats_warning")])
+  ]).unwrap();
+
+  let whats = backend.top_whats(&service, "error", 10).unwrap();
+  assert_eq!(whats, vec![("top_whats_macro".to_string(), 2)]);
+  assert!(backend.top_whats(&service, "info", 10).unwrap().is_empty());
+}
+
+#[test]
+fn slowest_tasks_ordering() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "duration test corpus");
+  let service = mock_service(&backend, "duration_test_service");
+  let mut reports = Vec::new();
+  for &(entry, duration) in vec![("/duration/fast.zip", 10), ("/duration/slow.zip", 300), ("/duration/medium.zip", 50)].iter() {
+    let task = mock_task(&backend, entry, &service, &corpus, TaskStatus::TODO);
+    let mut report = mock_report(&task, TaskStatus::NoProblem, Vec::new());
+    report.duration_ms = Some(duration);
+    reports.push(report);
+  }
+  backend.mark_done(&reports).unwrap();
+
+  let slowest = backend.slowest_tasks(&service, 2).unwrap();
+  let ranking = slowest.iter().map(|&(ref task, duration)| (task.entry.clone(), duration)).collect::<Vec<_>>();
+  assert_eq!(ranking, vec![("/duration/slow.zip".to_string(), 300), ("/duration/medium.zip".to_string(), 50)]);
+}
+
+#[test]
+fn rerun_tasks_by_id() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "rerun by id test corpus");
+  let service = mock_service(&backend, "rerun_by_id_test_service");
+  let first_task = mock_task(&backend, "/rerun_by_id/first.zip", &service, &corpus, TaskStatus::TODO);
+  let second_task = mock_task(&backend, "/rerun_by_id/second.zip", &service, &corpus, TaskStatus::TODO);
+  let untouched_task = mock_task(&backend, "/rerun_by_id/untouched.zip", &service, &corpus, TaskStatus::TODO);
+  backend.mark_done(&vec![
+    mock_report(&first_task, TaskStatus::Error, vec![("error", "rerun", "by_id")]),
+    mock_report(&second_task, TaskStatus::Fatal, vec![("fatal", "rerun", "by_id")]),
+    mock_report(&untouched_task, TaskStatus::Error, vec![("error", "rerun", "by_id")])
+  ]).unwrap();
+
+  // A previously staggered task is available right away once rerun
+  backend.connection.execute("UPDATE tasks SET available_at = extract(epoch from now())::bigint + 3600 WHERE taskid = $1",
+    &[&first_task.id.unwrap()]).unwrap();
+
+  let queued = backend.rerun_tasks(&[first_task.id.unwrap(), second_task.id.unwrap(), -42]).unwrap();
+  assert_eq!(queued, 2);
+  let scheduling_query = backend.connection.prepare("SELECT retries, available_at FROM tasks WHERE taskid = $1").unwrap();
+  let scheduling_rows = scheduling_query.query(&[&first_task.id.unwrap()]).unwrap();
+  let retries : i32 = scheduling_rows.get(0).get(0);
+  let available_at : Option<i64> = scheduling_rows.get(0).get(1);
+  assert_eq!((retries, available_at), (1, None));
+  assert_eq!(backend.sync(&first_task).unwrap().status, TaskStatus::TODO.raw());
+  assert_eq!(backend.sync(&second_task).unwrap().status, TaskStatus::TODO.raw());
+  assert_eq!(backend.sync(&untouched_task).unwrap().status, TaskStatus::Error.raw());
+  assert_eq!(backend.top_whats(&service, "error", 10).unwrap(), vec![("by_id".to_string(), 1)]);
+}
+
+#[test]
+fn rerun_keeps_log_history() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "log history test corpus");
+  let service = mock_service(&backend, "log_history_test_service");
+  let task = mock_task(&backend, "/log_history/entry.zip", &service, &corpus, TaskStatus::TODO);
+  backend.mark_done(&vec![mock_report(&task, TaskStatus::Error, vec![("error", "history", "first_run")])]).unwrap();
+
+  assert!(backend.mark_rerun_selected(&RerunSelector::new(&corpus, &service).severity("error").keep_history()).is_ok());
+  assert_eq!(backend.sync(&task).unwrap().status, TaskStatus::TODO.raw());
+  assert!(backend.top_whats(&service, "error", 10).unwrap().is_empty());
+
+  let history = backend.task_log_history(task.id.unwrap()).unwrap();
+  assert_eq!(history.len(), 1);
+  assert_eq!(history[0].1.what, "first_run");
+}
+
+#[test]
+fn register_service_on_complex_corpus() {
+  let backend = mock_backend();
+  let import_service = Service::from_name(&backend.connection, "import".to_string()).unwrap().unwrap();
+  let corpus = backend.add(
+    Corpus {
+      id : None,
+      name : "/register/complex/".to_string(),
+      path : "/register/complex/".to_string(),
+      complex : true
+    }).unwrap();
+  let service = mock_service(&backend, "register_complex_test_service");
+  mock_task(&backend, "/register/complex/a/a.zip", &import_service, &corpus, TaskStatus::NoProblem);
+  mock_task(&backend, "/register/complex/b/b.zip", &import_service, &corpus, TaskStatus::NoProblem);
+
+  assert!(backend.register_service(service.clone(), corpus.path.clone()).is_ok());
+  let registered = backend.fetch_tasks(&service, 10, false, None).unwrap();
+  assert_eq!(registered.len(), 2);
+}
+
+#[test]
+fn register_service_on_simple_corpus() {
+  let backend = mock_backend();
+  let mut corpus_dir = env::temp_dir();
+  corpus_dir.push("cortex_register_simple_test");
+  let corpus_path = corpus_dir.to_str().unwrap().to_string();
+  let _ = fs::remove_dir_all(&corpus_path);
+  for name in vec!["first", "second", "third"].iter() {
+    fs::create_dir_all(corpus_path.clone() + "/" + name).unwrap();
+    File::create(corpus_path.clone() + "/" + name + "/" + name + ".tex").unwrap();
+  }
+  let corpus = backend.add(
+    Corpus {
+      id : None,
+      name : corpus_path.clone(),
+      path : corpus_path.clone(),
+      complex : true
+    }).unwrap();
+  assert!(backend.set_corpus_complex(&corpus, false).is_ok());
+  let service = mock_service(&backend, "register_simple_test_service");
+
+  // No import tasks exist, the simple corpus is seeded from its files
+  assert!(backend.register_service(service.clone(), corpus_path.clone()).is_ok());
+  let registered = backend.fetch_tasks(&service, 10, false, None).unwrap();
+  assert_eq!(registered.len(), 3);
+  assert!(registered.iter().all(|task| task.entry.ends_with(".tex")));
+  // The entry files are recorded as imported, once, so the import-keyed lookups see them
+  assert!(backend.register_service(service.clone(), corpus_path.clone()).is_ok());
+  let hits = backend.search_entries("second/second.tex", 10).unwrap();
+  assert_eq!(hits.len(), 1);
+  assert!(hits[0].1.entry.ends_with("second.tex"));
+  assert!(backend.entries_missing_service(&corpus, &service).unwrap().is_empty());
+  assert!(backend.import_complete(&corpus).unwrap());
+  assert!(fs::remove_dir_all(&corpus_path).is_ok());
+}
+
+#[test]
+fn sampled_progress_report() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "sampled report test corpus");
+  let service = mock_service(&backend, "sampled_report_test_service");
+  let mut tasks = Vec::new();
+  for index in 0..400 {
+    tasks.push(Task {
+      id : None,
+      entry : format!("/sampled/{}.zip", index),
+      serviceid : service.id.unwrap(),
+      corpusid : corpus.id.unwrap(),
+      status : if index % 2 == 0 { TaskStatus::NoProblem.raw() } else { TaskStatus::Warning.raw() },
+      metadata : None
+    });
+  }
+  backend.mark_imported(&tasks).unwrap();
+
+  let exact = backend.progress_report(&corpus, &service);
+  let sampled = backend.progress_report_sampled(&corpus, &service, 0.5);
+  assert_eq!(sampled.get("sample_fraction"), Some(&0.5));
+  for key in vec!["no_problem_percent", "warning_percent"].iter() {
+    let difference = (exact.get(*key).unwrap() - sampled.get(*key).unwrap()).abs();
+    assert!(difference < 15.0, "{} differs by {}", key, difference);
+  }
+}
+
+#[test]
+fn ensure_services_idempotent() {
+  let backend = mock_backend();
+  let declared = vec!["ensure_test_a", "ensure_test_b", "ensure_test_c"].iter().map(|name| Service {
+    id : None,
+    name : name.to_string(),
+    version : 0.1,
+    inputformat : "tex".to_string(),
+    outputformat : "html".to_string(),
+    inputconverter : Some("import".to_string()),
+    complex : true
+  }).collect::<Vec<_>>();
+  assert!(backend.ensure_services(&declared).is_ok());
+  let service_a = backend.sync(&declared[0]).unwrap();
+  let corpus = mock_corpus(&backend, "ensure services test corpus");
+  let task = mock_task(&backend, "/ensure/entry.zip", &service_a, &corpus, TaskStatus::TODO);
+
+  let mut redeclared = declared.clone();
+  redeclared[0].outputformat = "xml".to_string();
+  assert!(backend.ensure_services(&redeclared).is_ok());
+
+  let count_query = backend.connection.prepare("SELECT count(*) FROM services WHERE name LIKE 'ensure_test_%'").unwrap();
+  let count : i64 = count_query.query(&[]).unwrap().get(0).get(0);
+  assert_eq!(count, 3);
+  let updated_a = backend.sync(&declared[0]).unwrap();
+  assert_eq!(updated_a.id, service_a.id);
+  assert_eq!(updated_a.outputformat, "xml");
+  assert_eq!(backend.sync(&task).unwrap().serviceid, service_a.id.unwrap());
+}
+
+#[test]
+fn rerun_candidates_respect_retry_limit() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "rerun candidates test corpus");
+  let service = mock_service(&backend, "rerun_candidates_test_service");
+  let mut tasks = Vec::new();
+  for &(entry, retries) in vec![("/candidates/fresh.zip", 0), ("/candidates/retried.zip", 2), ("/candidates/exhausted.zip", 3)].iter() {
+    let task = mock_task(&backend, entry, &service, &corpus, TaskStatus::Error);
+    backend.connection.execute("UPDATE tasks SET retries=$1 WHERE taskid=$2", &[&(retries as i32), &task.id.unwrap()]).unwrap();
+    tasks.push(task);
+  }
+  let fatal_task = mock_task(&backend, "/candidates/fatal.zip", &service, &corpus, TaskStatus::Fatal);
+
+  let candidates = backend.rerun_candidates(&corpus, &service, "error", 3).unwrap();
+  assert_eq!(candidates, vec![tasks[0].id.unwrap(), tasks[1].id.unwrap()]);
+  assert!(!candidates.contains(&fatal_task.id.unwrap()));
+
+  // Each rerun counts towards the cap
+  assert_eq!(backend.rerun_tasks(&candidates).unwrap(), 2);
+  backend.mark_done(&vec![mock_report(&tasks[1], TaskStatus::Error, Vec::new())]).unwrap();
+  assert_eq!(backend.rerun_candidates(&corpus, &service, "error", 3).unwrap(), Vec::<i64>::new());
+}
+
+#[test]
+fn needs_init_states() {
+  let backend = mock_backend();
+  assert_eq!(backend.needs_init().unwrap(), false);
+  {
+    // Uninitialized: roll back a transaction in which the Task store tables are gone
+    let trans = backend.connection.transaction().unwrap();
+    trans.execute("DROP TABLE services", &[]).unwrap();
+    assert_eq!(backend.needs_init().unwrap(), true);
+    trans.finish().unwrap();
+  }
+  {
+    // A failing Task store is an error, and not a reason to initialize
+    let trans = backend.connection.transaction().unwrap();
+    assert!(trans.execute("SELECT * FROM no_such_cortex_table", &[]).is_err());
+    assert!(backend.needs_init().is_err());
+    trans.finish().unwrap();
+  }
+  assert_eq!(backend.needs_init().unwrap(), false);
+}
+
+#[test]
+fn merge_overlapping_corpora() {
+  let backend = mock_backend();
+  let keep = mock_corpus(&backend, "merge keep test corpus");
+  let merge = mock_corpus(&backend, "merge duplicate test corpus");
+  let service = mock_service(&backend, "merge_test_service");
+  let kept_task = mock_task(&backend, "/merge/shared.zip", &service, &keep, TaskStatus::TODO);
+  let duplicate_task = mock_task(&backend, "/merge/shared.zip", &service, &merge, TaskStatus::TODO);
+  let moved_task = mock_task(&backend, "/merge/only_in_merge.zip", &service, &merge, TaskStatus::TODO);
+  backend.mark_done(&vec![
+    mock_report(&duplicate_task, TaskStatus::Error, vec![("error", "merge", "duplicate")]),
+    mock_report(&moved_task, TaskStatus::Error, vec![("error", "merge", "moved")])
+  ]).unwrap();
+
+  let summary = backend.merge_corpora(&keep, &merge).unwrap();
+  assert_eq!(summary, MergeSummary { moved : 1, deduped : 1 });
+  assert!(backend.corpora().iter().all(|corpus| corpus.id != merge.id));
+  assert_eq!(backend.sync(&kept_task).unwrap().corpusid, keep.id.unwrap());
+  assert_eq!(backend.sync(&moved_task).unwrap().corpusid, keep.id.unwrap());
+
+  let count_query = backend.connection.prepare("SELECT count(*) FROM tasks WHERE corpusid=$1 and entry='/merge/shared.zip'").unwrap();
+  let shared_count : i64 = count_query.query(&[&keep.id.unwrap()]).unwrap().get(0).get(0);
+  assert_eq!(shared_count, 1);
+  assert_eq!(backend.top_whats(&service, "error", 10).unwrap(), vec![("moved".to_string(), 1)]);
+}
+
+#[test]
+fn read_only_backend_reports() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "read only test corpus");
+  let service = mock_service(&backend, "read_only_test_service");
+  let task = mock_task(&backend, "/read_only/entry.zip", &service, &corpus, TaskStatus::TODO);
+  backend.mark_done(&vec![mock_report(&task, TaskStatus::Warning, vec![("warning", "read_only", "report")])]).unwrap();
+
+  // Only the reporting methods are available on a `ReadOnlyBackend`, mutations such as
+  // `setup_task_tables` or `mark_rerun` do not compile against it
+  let reader = ReadOnlyBackend::testdb();
+  assert!(reader.corpora().iter().any(|c| c.id == corpus.id));
+  assert!(reader.services().iter().any(|s| s.id == service.id));
+  let progress = reader.progress_report(&corpus, &service);
+  assert_eq!(progress.get("warning"), Some(&1.0));
+  let report = reader.task_report(&corpus, &service, Some("warning".to_string()), None, None);
+  assert_eq!(report[0].get("name"), Some(&"read_only".to_string()));
+}
+
+#[test]
+fn multiple_output_formats() {
+  let backend = mock_backend();
+  let mut service = mock_service(&backend, "multi_format_test_service");
+  assert_eq!(service.output_formats(), vec!["html".to_string()]);
+  service.id = None;
+  service.version = 0.2;
+  service.outputformat = "html, log,bbl".to_string();
+  let multi_format = backend.add(service).unwrap();
+  let synced = backend.sync(&multi_format).unwrap();
+  assert_eq!(synced.outputformat, "html, log,bbl");
+  assert_eq!(synced.output_formats(), vec!["html".to_string(), "log".to_string(), "bbl".to_string()]);
+}
+
+#[test]
+fn claim_specific_task() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "claim test corpus");
+  let service = mock_service(&backend, "claim_test_service");
+  let task = mock_task(&backend, "/claim/entry.zip", &service, &corpus, TaskStatus::TODO);
+
+  let claimed = backend.claim_task(task.id.unwrap()).unwrap().unwrap();
+  assert_eq!(claimed.id, task.id);
+  assert_eq!(claimed.entry, "/claim/entry.zip");
+  assert!(claimed.status >= 0);
+  assert!(backend.claim_task(task.id.unwrap()).unwrap().is_none());
+  assert!(backend.fetch_tasks(&service, 10, false, None).unwrap().is_empty());
+}
+
+#[test]
+fn entries_by_status_pagination() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "entries by status test corpus");
+  let service = mock_service(&backend, "entries_by_status_test_service");
+  let warnings = (0..5).map(|index| mock_task(&backend, &format!("/by_status/warning_{}.zip", index), &service, &corpus, TaskStatus::Warning))
+    .collect::<Vec<_>>();
+  mock_task(&backend, "/by_status/error.zip", &service, &corpus, TaskStatus::Error);
+
+  let first_page = backend.entries_by_status(&corpus, &service, TaskStatus::Warning, 3, 0).unwrap();
+  let second_page = backend.entries_by_status(&corpus, &service, TaskStatus::Warning, 3, 3).unwrap();
+  assert_eq!(first_page.len(), 3);
+  assert_eq!(second_page.len(), 2);
+  let listed = first_page.iter().chain(second_page.iter()).map(|task| task.id.unwrap()).collect::<Vec<_>>();
+  assert_eq!(listed, warnings.iter().map(|task| task.id.unwrap()).collect::<Vec<_>>());
+  assert!(first_page.iter().chain(second_page.iter()).all(|task| task.status == TaskStatus::Warning.raw()));
+}
+
+#[test]
+fn present_severities_only_warnings() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "present severities test corpus");
+  let service = mock_service(&backend, "present_severities_test_service");
+  mock_task(&backend, "/present/warning.zip", &service, &corpus, TaskStatus::Warning);
+  mock_task(&backend, "/present/second_warning.zip", &service, &corpus, TaskStatus::Warning);
+  mock_task(&backend, "/present/no_problem.zip", &service, &corpus, TaskStatus::NoProblem);
+  mock_task(&backend, "/present/todo.zip", &service, &corpus, TaskStatus::TODO);
+
+  assert_eq!(backend.present_severities(&corpus, &service).unwrap(), vec!["warning".to_string(), "no_problem".to_string()]);
+}
+
+#[test]
+fn mark_imported_in_chunks() {
+  let mut backend = mock_backend();
+  backend.import_commit_every = Some(3);
+  let corpus = mock_corpus(&backend, "chunked import test corpus");
+  let service = mock_service(&backend, "chunked_import_test_service");
+  let tasks = (0..10).map(|index| Task {
+    id : None,
+    entry : format!("/chunked/{}.zip", index),
+    serviceid : service.id.unwrap(),
+    corpusid : corpus.id.unwrap(),
+    status : TaskStatus::TODO.raw(),
+    metadata : None
+  }).collect::<Vec<_>>();
+  backend.mark_imported(&tasks).unwrap();
+
+  let count_query = backend.connection.prepare("SELECT count(*) FROM tasks WHERE corpusid=$1 and serviceid=$2").unwrap();
+  let count : i64 = count_query.query(&[&corpus.id.unwrap(), &service.id.unwrap()]).unwrap().get(0).get(0);
+  assert_eq!(count, 10);
+}
+
+#[test]
+fn task_report_cache() {
+  let backend = mock_backend();
+  // Caching is opt-in
+  assert_eq!(backend.report_cache.borrow().capacity, 0);
+  backend.report_cache.borrow_mut().capacity = 100;
+  let corpus = mock_corpus(&backend, "report cache test corpus");
+  let service = mock_service(&backend, "report_cache_test_service");
+  let first_task = mock_task(&backend, "/report_cache/first.zip", &service, &corpus, TaskStatus::TODO);
+  let second_task = mock_task(&backend, "/report_cache/second.zip", &service, &corpus, TaskStatus::TODO);
+  backend.mark_done(&vec![mock_report(&first_task, TaskStatus::Warning, vec![("warning", "cache", "first")])]).unwrap();
+  let (hits, misses) = backend.report_cache_stats();
+
+  let report = backend.task_report(&corpus, &service, Some("warning".to_string()), None, None);
+  let cached_report = backend.task_report(&corpus, &service, Some("warning".to_string()), None, None);
+  assert_eq!(report, cached_report);
+  assert_eq!(backend.report_cache_stats(), (hits + 1, misses + 1));
+
+  // Completing a task of the same corpus and service invalidates the cached report
+  backend.mark_done(&vec![mock_report(&second_task, TaskStatus::Warning, vec![("warning", "cache", "second")])]).unwrap();
+  let fresh_report = backend.task_report(&corpus, &service, Some("warning".to_string()), None, None);
+  assert_eq!(backend.report_cache_stats(), (hits + 1, misses + 2));
+  assert_eq!(fresh_report[0].get("tasks"), Some(&"2".to_string()));
+
+  // So does forcing the status of one of its tasks
+  backend.set_task_status(second_task.id.unwrap(), TaskStatus::Error).unwrap();
+  let forced_report = backend.task_report(&corpus, &service, Some("warning".to_string()), None, None);
+  assert_eq!(backend.report_cache_stats(), (hits + 1, misses + 3));
+  assert_eq!(forced_report[0].get("tasks"), Some(&"1".to_string()));
+}
+
+#[test]
+fn rename_service_keeps_tasks() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "rename service test corpus");
+  let taken = mock_service(&backend, "rename_test_taken");
+  let service = mock_service(&backend, "rename_test_original");
+  // Clean up after any earlier run
+  if let Some(stale) = Service::from_name(&backend.connection, "rename_test_renamed".to_string()).unwrap() {
+    backend.delete(&stale).unwrap();
+  }
+  let task = mock_task(&backend, "/rename/entry.zip", &service, &corpus, TaskStatus::TODO);
+
+  match backend.rename_service(&service, "rename_test_taken") {
+    Err(CortexError::AlreadyExists(_)) => {},
+    other => panic!("Expected an AlreadyExists error, got {:?}", other)
+  }
+  assert!(backend.rename_service(&service, "rename_test_renamed").is_ok());
+  let renamed = Service::from_name(&backend.connection, "rename_test_renamed".to_string()).unwrap().unwrap();
+  assert_eq!(renamed.id, service.id);
+  assert!(Service::from_name(&backend.connection, "rename_test_original".to_string()).unwrap().is_none());
+  assert_eq!(backend.sync(&task).unwrap().serviceid, renamed.id.unwrap());
+  assert!(taken.id != renamed.id);
+}
+
+#[test]
+fn peek_tasks_without_claiming() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "peek tasks test corpus");
+  let service = mock_service(&backend, "peek_tasks_test_service");
+  for index in 0..5 {
+    mock_task(&backend, &format!("/peek/{}.zip", index), &service, &corpus, TaskStatus::TODO);
+  }
+
+  let peeked = backend.peek_tasks(&service, 3).unwrap();
+  assert_eq!(peeked.len(), 3);
+  assert!(peeked.iter().all(|task| backend.sync(task).unwrap().status == TaskStatus::TODO.raw()));
+  assert_eq!(backend.peek_tasks(&service, 3).unwrap().iter().map(|task| task.id).collect::<Vec<_>>(),
+    peeked.iter().map(|task| task.id).collect::<Vec<_>>());
+
+  let fetched = backend.fetch_tasks(&service, 3, false, None).unwrap();
+  let mut fetched_ids = fetched.iter().map(|task| task.id.unwrap()).collect::<Vec<_>>();
+  fetched_ids.sort();
+  assert_eq!(fetched_ids, peeked.iter().map(|task| task.id.unwrap()).collect::<Vec<_>>());
+}
+
+#[test]
+fn statuses_for_entry_batch() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "entry statuses test corpus");
+  let service = mock_service(&backend, "entry_statuses_test_service");
+  mock_task(&backend, "/statuses/warning.zip", &service, &corpus, TaskStatus::Warning);
+  mock_task(&backend, "/statuses/todo.zip", &service, &corpus, TaskStatus::TODO);
+
+  let entries = vec!["/statuses/warning.zip", "/statuses/todo.zip", "/statuses/unknown.zip"].iter()
+    .map(|entry| entry.to_string()).collect::<Vec<_>>();
+  let statuses = backend.statuses_for_entries(&corpus, &service, &entries).unwrap();
+  assert_eq!(statuses.len(), 2);
+  assert_eq!(statuses.get("/statuses/warning.zip").map(|status| status.raw()), Some(TaskStatus::Warning.raw()));
+  assert_eq!(statuses.get("/statuses/todo.zip").map(|status| status.raw()), Some(TaskStatus::TODO.raw()));
+  assert!(statuses.get("/statuses/unknown.zip").is_none());
+}
+
+#[test]
+fn distinct_entry_count_across_services() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "distinct entries test corpus");
+  let first_service = mock_service(&backend, "distinct_entries_first_service");
+  let second_service = mock_service(&backend, "distinct_entries_second_service");
+  for entry in vec!["/distinct/a.zip", "/distinct/b.zip", "/distinct/c.zip"].iter() {
+    mock_task(&backend, entry, &first_service, &corpus, TaskStatus::TODO);
+    mock_task(&backend, entry, &second_service, &corpus, TaskStatus::TODO);
+  }
+  assert_eq!(backend.distinct_entry_count(&corpus).unwrap(), 3);
+}
+
+#[test]
+fn with_transaction_rolls_back_on_error() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "transaction test corpus");
+  let service = mock_service(&backend, "transaction_test_service");
+  let result : Result<(), CortexError> = backend.with_transaction(|trans| {
+    try!(trans.execute("INSERT INTO tasks (entry,serviceid,corpusid,status) VALUES ($1,$2,$3,$4)",
+      &[&"/transaction/rolled_back.zip", &service.id.unwrap(), &corpus.id.unwrap(), &TaskStatus::TODO.raw()]));
+    Err(CortexError::Protocol("abort the transaction".to_string()))
+  });
+  assert!(result.is_err());
+  let committed = backend.with_transaction(|trans| {
+    try!(trans.execute("INSERT INTO tasks (entry,serviceid,corpusid,status) VALUES ($1,$2,$3,$4)",
+      &[&"/transaction/committed.zip", &service.id.unwrap(), &corpus.id.unwrap(), &TaskStatus::TODO.raw()]));
+    Ok(42)
+  });
+  assert_eq!(committed.unwrap(), 42);
+
+  let count_query = backend.connection.prepare("SELECT entry FROM tasks WHERE corpusid=$1").unwrap();
+  let entries = count_query.query(&[&corpus.id.unwrap()]).unwrap().iter()
+    .map(|row| { let entry : String = row.get(0); entry.trim_right().to_string() }).collect::<Vec<_>>();
+  assert_eq!(entries, vec!["/transaction/committed.zip".to_string()]);
+}
+
+#[test]
+fn set_task_status_variants() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "set status test corpus");
+  let service = mock_service(&backend, "set_status_test_service");
+  let task = mock_task(&backend, "/set_status/entry.zip", &service, &corpus, TaskStatus::Error);
+  for status in vec![TaskStatus::NoProblem, TaskStatus::Warning, TaskStatus::Fatal, TaskStatus::TODO, TaskStatus::Blocked(-7), TaskStatus::Queued(3)].into_iter() {
+    assert!(backend.set_task_status(task.id.unwrap(), status.clone()).is_ok());
+    assert_eq!(backend.sync(&task).unwrap().status, status.raw());
+  }
+  match backend.set_task_status(task.id.unwrap(), TaskStatus::Queued(-3)) {
+    Err(CortexError::Invalid(_)) => {},
+    other => panic!("Expected an Invalid error, got {:?}", other)
+  }
+  assert_eq!(backend.sync(&task).unwrap().status, TaskStatus::Queued(3).raw());
+
+  // A forced status is reflected in a previously cached report
+  backend.report_cache.borrow_mut().capacity = 100;
+  assert!(backend.set_task_status(task.id.unwrap(), TaskStatus::NoProblem).is_ok());
+  assert_eq!(backend.task_report(&corpus, &service, Some("no_problem".to_string()), None, None).len(), 1);
+  assert!(backend.set_task_status(task.id.unwrap(), TaskStatus::Warning).is_ok());
+  assert!(backend.task_report(&corpus, &service, Some("no_problem".to_string()), None, None).is_empty());
+}
+
+#[test]
+fn progress_report_excluding_todo() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "filtered progress test corpus");
+  let service = mock_service(&backend, "filtered_progress_test_service");
+  for &(entry, ref status) in vec![("/filtered/a.zip", TaskStatus::NoProblem), ("/filtered/b.zip", TaskStatus::Warning),
+    ("/filtered/c.zip", TaskStatus::Warning), ("/filtered/d.zip", TaskStatus::Error), ("/filtered/e.zip", TaskStatus::TODO),
+    ("/filtered/f.zip", TaskStatus::TODO)].iter() {
+    mock_task(&backend, entry, &service, &corpus, status.clone());
+  }
+
+  let report = backend.progress_report_filtered(&corpus, &service, &[TaskStatus::TODO]);
+  assert!(report.get("todo").is_none());
+  assert_eq!(report.get("total"), Some(&4.0));
+  assert_eq!(report.get("warning_percent"), Some(&50.0));
+  let percent_sum = report.iter().filter(|&(key, _)| key.ends_with("_percent") && key != "total_percent")
+    .fold(0.0, |sum, (_, percent)| sum + percent);
+  assert!((percent_sum - 100.0).abs() < 0.1, "percentages sum to {}", percent_sum);
+  // The unfiltered report is unchanged
+  assert_eq!(backend.progress_report(&corpus, &service).get("total"), Some(&6.0));
+}
+
+#[test]
+fn register_service_by_input_format() {
+  let backend = mock_backend();
+  let mut corpus_dir = env::temp_dir();
+  corpus_dir.push("cortex_register_mixed_test");
+  let corpus_path = corpus_dir.to_str().unwrap().to_string();
+  let _ = fs::remove_dir_all(&corpus_path);
+  for &(name, ref extensions) in vec![("first", vec!["tex", "bib"]), ("second", vec!["pdf"]), ("third", vec!["tex", "pdf"])].iter() {
+    fs::create_dir_all(corpus_path.clone() + "/" + name).unwrap();
+    for extension in extensions.iter() {
+      File::create(corpus_path.clone() + "/" + name + "/" + name + "." + extension).unwrap();
+    }
+  }
+  let corpus = backend.add(
+    Corpus {
+      id : None,
+      name : corpus_path.clone(),
+      path : corpus_path.clone(),
+      complex : false
+    }).unwrap();
+  let tex_service = mock_service(&backend, "register_mixed_tex_service");
+  let mut pdf_service = mock_service(&backend, "register_mixed_pdf_service");
+  pdf_service.inputformat = "pdf".to_string();
+
+  assert!(backend.register_service(tex_service.clone(), corpus_path.clone()).is_ok());
+  let tex_tasks = backend.fetch_tasks(&tex_service, 10, false, None).unwrap();
+  assert_eq!(tex_tasks.len(), 2);
+  assert!(tex_tasks.iter().all(|task| task.entry.ends_with(".tex")));
+  assert!(backend.register_service(pdf_service.clone(), corpus_path.clone()).is_ok());
+  let pdf_tasks = backend.fetch_tasks(&pdf_service, 10, false, None).unwrap();
+  assert_eq!(pdf_tasks.len(), 2);
+  assert!(pdf_tasks.iter().all(|task| task.entry.ends_with(".pdf")));
+  assert!(backend.delete(&corpus).is_ok());
+  assert!(fs::remove_dir_all(&corpus_path).is_ok());
+}
+
+#[test]
+fn archive_old_logs() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "archive logs test corpus");
+  let service = mock_service(&backend, "archive_logs_test_service");
+  let old_task = mock_task(&backend, "/archive/old.zip", &service, &corpus, TaskStatus::TODO);
+  let new_task = mock_task(&backend, "/archive/new.zip", &service, &corpus, TaskStatus::TODO);
+  backend.mark_done(&vec![
+    mock_report(&old_task, TaskStatus::Error, vec![("error", "archive", "old_first"), ("warning", "archive", "old_second")]),
+    mock_report(&new_task, TaskStatus::Error, vec![("error", "archive", "new")])
+  ]).unwrap();
+
+  let mut archive : Vec<u8> = Vec::new();
+  let archived = backend.archive_logs(new_task.id.unwrap(), &mut archive).unwrap();
+  let archive_lines = String::from_utf8(archive).unwrap().lines().map(|line| line.to_string()).collect::<Vec<_>>();
+  assert!(archived >= 2);
+  assert_eq!(archive_lines.len() as u64, archived);
+  assert!(archive_lines.iter().any(|line| line.contains("old_first")));
+  assert!(!archive_lines.iter().any(|line| line.contains("\"new\"")));
+
+  let remaining_query = backend.connection.prepare("SELECT count(*) FROM logs WHERE taskid = $1").unwrap();
+  let old_remaining : i64 = remaining_query.query(&[&old_task.id.unwrap()]).unwrap().get(0).get(0);
+  let new_remaining : i64 = remaining_query.query(&[&new_task.id.unwrap()]).unwrap().get(0).get(0);
+  assert_eq!(old_remaining, 0);
+  assert_eq!(new_remaining, 1);
+}
+
+#[test]
+fn task_report_by_names() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "report by names test corpus");
+  let service = mock_service(&backend, "report_by_names_test_service");
+  let task = mock_task(&backend, "/by_names/entry.zip", &service, &corpus, TaskStatus::TODO);
+  backend.mark_done(&vec![mock_report(&task, TaskStatus::Warning, vec![("warning", "by_names", "named")])]).unwrap();
+
+  let by_ids = backend.task_report(&corpus, &service, Some("warning".to_string()), None, None);
+  let by_names = backend.report_by_names("report by names test corpus", "report_by_names_test_service",
+    Some("warning".to_string()), None, None).unwrap();
+  assert!(!by_names.is_empty());
+  assert_eq!(by_names, by_ids);
+
+  match backend.report_by_names("no such corpus", "report_by_names_test_service", None, None, None) {
+    Err(CortexError::Invalid(_)) => {},
+    _ => panic!("an unknown corpus name should be an invalid argument")
+  }
+}
+
+#[test]
+fn priority_scoped_fetch_order() {
+  let backend = mock_backend();
+  let service = mock_service(&backend, "priority_test_service");
+  let regular_corpus = mock_corpus(&backend, "regular priority test corpus");
+  let boosted_corpus = mock_corpus(&backend, "boosted priority test corpus");
+  mock_task(&backend, "/priority/regular.zip", &service, &regular_corpus, TaskStatus::TODO);
+  let boosted_task = mock_task(&backend, "/priority/boosted.zip", &service, &boosted_corpus, TaskStatus::TODO);
+
+  assert_eq!(backend.set_priority_scoped(&boosted_corpus, &service, 10).unwrap(), 1);
+  let fetched = backend.fetch_tasks(&service, 1, false, None).unwrap();
+  assert_eq!(fetched.len(), 1);
+  assert_eq!(fetched[0].id, boosted_task.id);
+  assert_eq!(fetched[0].corpusid, boosted_corpus.id.unwrap());
+}
+
+#[test]
+fn add_task_if_missing_preserves_existing() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "add if missing test corpus");
+  let service = mock_service(&backend, "add_if_missing_test_service");
+  let task = mock_task(&backend, "/if_missing/done.zip", &service, &corpus, TaskStatus::TODO);
+  backend.mark_done(&vec![mock_report(&task, TaskStatus::Warning, vec![("warning", "if_missing", "kept")])]).unwrap();
+
+  let readded = backend.add_task_if_missing(Task::new("/if_missing/done.zip", &service, &corpus)).unwrap();
+  assert_eq!(readded.id, task.id);
+  assert_eq!(readded.status, TaskStatus::Warning.raw());
+
+  let fresh = backend.add_task_if_missing(Task::new("/if_missing/fresh.zip", &service, &corpus)).unwrap();
+  assert!(fresh.id.is_some());
+  assert_eq!(fresh.status, TaskStatus::TODO.raw());
+  assert!(backend.delete(&fresh).is_ok());
+}
+
+#[test]
+fn weighted_corpus_health() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "corpus health test corpus");
+  let half_service = mock_service(&backend, "half_health_test_service");
+  let full_service = mock_service(&backend, "full_health_test_service");
+  mock_task(&backend, "/health/first.zip", &half_service, &corpus, TaskStatus::NoProblem);
+  mock_task(&backend, "/health/second.zip", &half_service, &corpus, TaskStatus::Error);
+  mock_task(&backend, "/health/first.zip", &full_service, &corpus, TaskStatus::NoProblem);
+
+  assert_eq!(backend.corpus_health(&corpus, None).unwrap(), 75.0);
+  let mut weights = HashMap::new();
+  weights.insert("half_health_test_service".to_string(), 3.0);
+  weights.insert("full_health_test_service".to_string(), 1.0);
+  assert_eq!(backend.corpus_health(&corpus, Some(&weights)).unwrap(), 62.5);
+}
+
+#[test]
+fn cancelled_task_drops_late_report() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "cancel task test corpus");
+  let service = mock_service(&backend, "cancel_task_test_service");
+  let task = mock_task(&backend, "/cancel/entry.zip", &service, &corpus, TaskStatus::TODO);
+  let claimed_task = backend.claim_task(task.id.unwrap()).unwrap().unwrap();
+
+  assert!(backend.cancel_task(claimed_task.id.unwrap()).is_ok());
+  assert_eq!(backend.sync(&task).unwrap().status, TaskStatus::Cancelled.raw());
+  // The worker reports back after the cancellation
+  backend.mark_done(&vec![mock_report(&claimed_task, TaskStatus::Error, vec![("error", "cancel", "late")])]).unwrap();
+  assert_eq!(backend.sync(&task).unwrap().status, TaskStatus::Cancelled.raw());
+  let log_count : i64 = backend.connection.prepare("SELECT count(*) FROM logs WHERE taskid = $1").unwrap()
+    .query(&[&task.id.unwrap()]).unwrap().get(0).get(0);
+  assert_eq!(log_count, 0);
+
+  // Only in progress tasks can be cancelled
+  match backend.cancel_task(task.id.unwrap()) {
+    Err(CortexError::Invalid(_)) => {},
+    _ => panic!("a cancelled task should not be cancellable again")
+  }
+}
+
+#[test]
+fn dependents_to_rerun_chain() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "dependents test corpus");
+  let foundation = mock_service(&backend, "foundation_dependents_test_service");
+  let master = mock_service(&backend, "master_dependents_test_service");
+  let grand_master = mock_service(&backend, "grand_master_dependents_test_service");
+  let unrelated = mock_service(&backend, "unrelated_dependents_test_service");
+  for &(ref dependent, ref dependency) in vec![(&master, &foundation), (&grand_master, &master)].iter() {
+    backend.connection.execute("INSERT INTO dependencies (master, foundation) values($1, $2)",
+      &[&dependent.id.unwrap(), &dependency.id.unwrap()]).unwrap();
+  }
+  mock_task(&backend, "/dependents/first.zip", &foundation, &corpus, TaskStatus::NoProblem);
+  mock_task(&backend, "/dependents/second.zip", &foundation, &corpus, TaskStatus::Warning);
+  let master_stale = mock_task(&backend, "/dependents/first.zip", &master, &corpus, TaskStatus::NoProblem);
+  mock_task(&backend, "/dependents/third.zip", &master, &corpus, TaskStatus::NoProblem);
+  let grand_master_stale = mock_task(&backend, "/dependents/second.zip", &grand_master, &corpus, TaskStatus::Error);
+  mock_task(&backend, "/dependents/first.zip", &grand_master, &corpus, TaskStatus::TODO);
+  mock_task(&backend, "/dependents/first.zip", &unrelated, &corpus, TaskStatus::NoProblem);
+
+  let dependents = backend.dependents_to_rerun(&foundation).unwrap();
+  assert_eq!(dependents.len(), 2);
+  assert_eq!(dependents[0].0.id, master.id);
+  assert_eq!(dependents[0].1, vec![master_stale.id.unwrap()]);
+  assert_eq!(dependents[1].0.id, grand_master.id);
+  assert_eq!(dependents[1].1, vec![grand_master_stale.id.unwrap()]);
+  // Don't leave a format-mismatched pipeline behind for the manager tests
+  backend.connection.execute("DELETE FROM dependencies WHERE foundation = $1 or foundation = $2",
+    &[&foundation.id.unwrap(), &master.id.unwrap()]).unwrap();
+}
+
+#[test]
+fn relative_entries_follow_corpus_mount() {
+  let mut backend = mock_backend();
+  backend.relative_entries = true;
+  let first_root = "/mnt/first_root/relative_corpus".to_string();
+  let corpus = backend.add(
+    Corpus {
+      id : None,
+      name : first_root.clone(),
+      path : first_root.clone(),
+      complex : true
+    }).unwrap();
+  let service = mock_service(&backend, "relative_entries_test_service");
+  backend.mark_imported(&vec![Task::imported(&(first_root.clone() + "/1501.0001/1501.0001.zip"), &corpus)]).unwrap();
+  let stored_query = backend.connection.prepare("SELECT entry FROM tasks WHERE serviceid=2 and corpusid=$1").unwrap();
+  let stored_entry : String = stored_query.query(&[&corpus.id.unwrap()]).unwrap().get(0).get(0);
+  assert_eq!(stored_entry.trim_right(), "1501.0001/1501.0001.zip");
+  assert!(backend.register_service(service.clone(), first_root.clone()).is_ok());
+
+  // The corpus is now mounted elsewhere, dispatched entries follow it
+  backend.connection.execute("UPDATE corpora SET path='/mnt/second_root/relative_corpus' WHERE corpusid=$1",
+    &[&corpus.id.unwrap()]).unwrap();
+  let peeked = backend.peek_tasks(&service, 10).unwrap();
+  assert_eq!(peeked[0].entry, "/mnt/second_root/relative_corpus/1501.0001/1501.0001.zip");
+  let dispatched = backend.fetch_tasks(&service, 10, false, None).unwrap();
+  assert_eq!(dispatched.len(), 1);
+  assert_eq!(dispatched[0].entry, "/mnt/second_root/relative_corpus/1501.0001/1501.0001.zip");
+}
+
+#[test]
+fn in_progress_tasks_with_ages() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "in progress test corpus");
+  let service = mock_service(&backend, "in_progress_test_service");
+  let task = mock_task(&backend, "/in_progress/entry.zip", &service, &corpus, TaskStatus::TODO);
+  backend.claim_task(task.id.unwrap()).unwrap().unwrap();
+  backend.connection.execute("UPDATE tasks SET claimed_at = claimed_at - 1000000000 WHERE taskid = $1", &[&task.id.unwrap()]).unwrap();
+
+  let in_progress = backend.in_progress_tasks().unwrap();
+  // Backdated by over 30 years, this is the oldest claimed task
+  let &(ref oldest_task, ref age) = in_progress.first().unwrap();
+  assert_eq!(oldest_task.id, task.id);
+  assert!(age.as_secs() >= 1000000000 && age.as_secs() < 1000000060);
+  assert!(backend.delete(&task).is_ok());
+}
+
+#[test]
+fn export_task_manifest() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "manifest test corpus");
+  let service = mock_service(&backend, "manifest_test_service");
+  mock_task(&backend, "/manifest/ok.zip", &service, &corpus, TaskStatus::NoProblem);
+  mock_task(&backend, "/manifest/error.zip", &service, &corpus, TaskStatus::Error);
+  mock_task(&backend, "/manifest/todo.zip", &service, &corpus, TaskStatus::TODO);
+
+  let mut manifest : Vec<u8> = Vec::new();
+  assert_eq!(backend.export_manifest(&corpus, &service, &mut manifest).unwrap(), 3);
+  let manifest_entries = String::from_utf8(manifest).unwrap().lines().map(|line| {
+    let fields = line.split('\t').collect::<Vec<_>>();
+    assert_eq!(fields.len(), 2);
+    (fields[0].to_string(), fields[1].to_string())
+  }).collect::<Vec<_>>();
+  assert_eq!(manifest_entries, vec![
+    ("/manifest/ok.zip".to_string(), "no_problem".to_string()),
+    ("/manifest/error.zip".to_string(), "error".to_string()),
+    ("/manifest/todo.zip".to_string(), "todo".to_string())]);
+}
+
+#[test]
+fn service_formats_validation() {
+  let mut backend = mock_backend();
+  let mut typo_service = Service {
+    id : None,
+    name : "format_validation_test_service".to_string(),
+    version : 0.1,
+    inputformat : "tex".to_string(),
+    outputformat : "htlm".to_string(),
+    inputconverter : Some("import".to_string()),
+    complex : true
+  };
+  match backend.add(typo_service.clone()) {
+    Err(CortexError::Invalid(_)) => {},
+    _ => panic!("a service with an unknown output format should be rejected")
+  }
+  match backend.ensure_services(&[typo_service.clone()]) {
+    Err(CortexError::Invalid(_)) => {},
+    _ => panic!("a service with an unknown output format should be rejected")
+  }
+  assert!(Service::from_name(&backend.connection, typo_service.name.clone()).unwrap().is_none());
+
+  typo_service.outputformat = "html".to_string();
+  assert!(typo_service.validate_formats(&backend.formats).is_ok());
+  // Custom formats can be registered
+  typo_service.outputformat = "htlm".to_string();
+  backend.formats.register("htlm");
+  let registered_service = backend.add(typo_service).unwrap();
+  assert!(backend.delete(&registered_service).is_ok());
+}
+
+#[test]
+fn requeue_all_confirmed() {
+  let backend = mock_backend();
+  let service = mock_service(&backend, "requeue_all_test_service");
+  let first_corpus = mock_corpus(&backend, "requeue all test corpus A");
+  let second_corpus = mock_corpus(&backend, "requeue all test corpus B");
+  let first_task = mock_task(&backend, "/requeue_all/a.zip", &service, &first_corpus, TaskStatus::TODO);
+  let second_task = mock_task(&backend, "/requeue_all/b.zip", &service, &second_corpus, TaskStatus::TODO);
+  backend.mark_done(&vec![
+    mock_report(&first_task, TaskStatus::Error, vec![("error", "requeue_all", "first")]),
+    mock_report(&second_task, TaskStatus::Warning, vec![("warning", "requeue_all", "second")])
+  ]).unwrap();
+
+  assert_eq!(backend.requeue_all(&service, "requeue_all_test_service").unwrap(), 2);
+  assert_eq!(backend.sync(&first_task).unwrap().status, TaskStatus::TODO.raw());
+  // Retries are counted just as by the other reruns
+  let retries : i32 = backend.connection.prepare("SELECT retries FROM tasks WHERE taskid = $1").unwrap()
+    .query(&[&first_task.id.unwrap()]).unwrap().get(0).get(0);
+  assert_eq!(retries, 1);
+  assert_eq!(backend.sync(&second_task).unwrap().status, TaskStatus::TODO.raw());
+  let log_count : i64 = backend.connection.prepare("SELECT count(*) FROM logs WHERE taskid = ANY($1)").unwrap()
+    .query(&[&vec![first_task.id.unwrap(), second_task.id.unwrap()]]).unwrap().get(0).get(0);
+  assert_eq!(log_count, 0);
+}
+
+#[test]
+fn requeue_all_unconfirmed() {
+  let backend = mock_backend();
+  let service = mock_service(&backend, "requeue_all_unconfirmed_test_service");
+  let corpus = mock_corpus(&backend, "requeue all unconfirmed test corpus");
+  let task = mock_task(&backend, "/requeue_all/unconfirmed.zip", &service, &corpus, TaskStatus::NoProblem);
+
+  match backend.requeue_all(&service, "requeue_all_test_service") {
+    Err(CortexError::Invalid(_)) => {},
+    _ => panic!("requeueing with a mismatched confirmation should fail")
+  }
+  assert_eq!(backend.sync(&task).unwrap().status, TaskStatus::NoProblem.raw());
+}
+
+#[test]
+fn tasks_by_worker_listing() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "worker attribution test corpus");
+  let service = mock_service(&backend, "worker_attribution_test_service");
+  let mut reports = Vec::new();
+  let mut tasks = Vec::new();
+  for &(entry, worker_id) in vec![("/worker/first.zip", "worker-alpha"), ("/worker/second.zip", "worker-beta"),
+    ("/worker/third.zip", "worker-alpha")].iter() {
+    let task = mock_task(&backend, entry, &service, &corpus, TaskStatus::TODO);
+    let mut report = mock_report(&task, TaskStatus::NoProblem, Vec::new());
+    report.worker_id = Some(worker_id.to_string());
+    reports.push(report);
+    tasks.push(task);
+  }
+  backend.mark_done(&reports).unwrap();
+
+  let alpha_tasks = backend.tasks_by_worker("worker-alpha").unwrap().into_iter()
+    .filter(|task| task.corpusid == corpus.id.unwrap()).map(|task| task.id).collect::<Vec<_>>();
+  assert_eq!(alpha_tasks, vec![tasks[0].id, tasks[2].id]);
+  let beta_tasks = backend.tasks_by_worker("worker-beta").unwrap().into_iter()
+    .filter(|task| task.corpusid == corpus.id.unwrap()).map(|task| task.id).collect::<Vec<_>>();
+  assert_eq!(beta_tasks, vec![tasks[1].id]);
+}
+
+#[test]
+fn artifact_formats_recorded() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "artifact formats test corpus");
+  let service = mock_service(&backend, "artifact_formats_test_service");
+  let task = mock_task(&backend, "/artifacts/entry.zip", &service, &corpus, TaskStatus::TODO);
+  let mut report = mock_report(&task, TaskStatus::NoProblem, Vec::new());
+  report.artifact_formats = vec!["html".to_string(), "xml".to_string()];
+  backend.mark_done(&vec![report]).unwrap();
+
+  let recorded : Option<String> = backend.connection.prepare("SELECT artifact_formats FROM tasks WHERE taskid=$1").unwrap()
+    .query(&[&task.id.unwrap()]).unwrap().get(0).get(0);
+  assert_eq!(recorded, Some("html,xml".to_string()));
+}
+
+#[test]
+fn latest_service_versions() {
+  let backend = mock_backend();
+  let old_service = mock_service(&backend, "latest_versions_test_service");
+  let mut new_service = old_service.clone();
+  new_service.id = None;
+  new_service.version = 0.2;
+  let new_service = backend.add(new_service).unwrap();
+
+  let versions = backend.latest_versions().unwrap();
+  assert_eq!(versions.get("latest_versions_test_service"), Some(&0.2));
+  assert_eq!(versions.get("import"), Some(&0.1));
+  assert!(backend.delete(&new_service).is_ok());
+}
+
+#[test]
+fn dedupe_duplicate_tasks() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "dedupe test corpus");
+  let service = mock_service(&backend, "dedupe_test_service");
+  // backend.add would overwrite, so insert the duplicates directly
+  let insert_query = backend.connection.prepare("INSERT INTO tasks (entry,serviceid,corpusid,status) VALUES ($1,$2,$3,$4)").unwrap();
+  let duplicate_entry = "/dedupe/duplicate.zip".to_string();
+  for status in vec![TaskStatus::TODO, TaskStatus::Error, TaskStatus::Warning, TaskStatus::TODO].iter() {
+    insert_query.execute(&[&duplicate_entry, &service.id.unwrap(), &corpus.id.unwrap(), &status.raw()]).unwrap();
+  }
+  mock_task(&backend, "/dedupe/single.zip", &service, &corpus, TaskStatus::Fatal);
+
+  assert_eq!(backend.dedupe_tasks(&corpus, &service).unwrap(), 3);
+  let survivors_query = backend.connection.prepare("SELECT entry, status FROM tasks WHERE corpusid=$1 and serviceid=$2 ORDER BY entry").unwrap();
+  let survivors = survivors_query.query(&[&corpus.id.unwrap(), &service.id.unwrap()]).unwrap().iter().map(|row| {
+    let entry : String = row.get(0);
+    let status : i32 = row.get(1);
+    (entry.trim_right().to_string(), status)
+  }).collect::<Vec<_>>();
+  assert_eq!(survivors, vec![
+    ("/dedupe/duplicate.zip".to_string(), TaskStatus::Warning.raw()),
+    ("/dedupe/single.zip".to_string(), TaskStatus::Fatal.raw())]);
+}
+
+#[test]
+fn task_report_page_totals() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "report page test corpus");
+  let service = mock_service(&backend, "report_page_test_service");
+  let mut reports = Vec::new();
+  for index in 0..5 {
+    let task = mock_task(&backend, &format!("/report_page/{}.zip", index), &service, &corpus, TaskStatus::TODO);
+    reports.push(mock_report(&task, TaskStatus::Error, vec![("error", "paging", "paged")]));
+  }
+  mock_task(&backend, "/report_page/ok.zip", &service, &corpus, TaskStatus::NoProblem);
+  backend.mark_done(&reports).unwrap();
+
+  let (first_page, first_total) = backend.task_report_page(&corpus, &service, "error".to_string(),
+    Some("paging".to_string()), Some("paged".to_string()), 2, 0).unwrap();
+  let (last_page, last_total) = backend.task_report_page(&corpus, &service, "error".to_string(),
+    Some("paging".to_string()), Some("paged".to_string()), DEFAULT_ENTRY_LIMIT, 4).unwrap();
+  assert_eq!(first_page.len(), 2);
+  assert_eq!(last_page.len(), 1);
+  assert_eq!(first_total, 5);
+  assert_eq!(last_total, 5);
+
+  let (ok_page, ok_total) = backend.task_report_page(&corpus, &service, "no_problem".to_string(), None, None, 0, 0).unwrap();
+  assert!(ok_page.is_empty());
+  assert_eq!(ok_total, 1);
+}
+
+#[test]
+fn task_report_stream_matches_batch() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "report stream test corpus");
+  let service = mock_service(&backend, "report_stream_test_service");
+  let mut reports = Vec::new();
+  for (index, &(category, what)) in vec![("stream_a", "first"), ("stream_a", "second"), ("stream_a", "first"),
+    ("stream_b", "first")].iter().enumerate() {
+    let task = mock_task(&backend, &format!("/report_stream/{}.zip", index), &service, &corpus, TaskStatus::TODO);
+    reports.push(mock_report(&task, TaskStatus::Error, vec![("error", category, what)]));
+  }
+  backend.mark_done(&reports).unwrap();
+
+  // Distinct counts per category and what, so that the report order is unambiguous
+  for &(ref category, ref what) in vec![(None, None), (Some("stream_a".to_string()), None),
+    (Some("stream_a".to_string()), Some("first".to_string()))].iter() {
+    let batch = backend.task_report(&corpus, &service, Some("error".to_string()), category.clone(), what.clone());
+    let mut streamed = Vec::new();
+    backend.task_report_stream(&corpus, &service, Some("error".to_string()), category.clone(), what.clone(),
+      |row| streamed.push(row)).unwrap();
+    assert!(!streamed.is_empty());
+    assert_eq!(streamed, batch);
+  }
+}
+
+#[test]
+fn import_progress_callback() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "import progress test corpus");
+  let tasks = (0..10).map(|index| Task::imported(&format!("/import_progress/{}.zip", index), &corpus)).collect::<Vec<_>>();
+
+  let mut progress = Vec::new();
+  backend.mark_imported_with_progress(&tasks, 3, |done, total| progress.push((done, total))).unwrap();
+  assert_eq!(progress, vec![(3, 10), (6, 10), (9, 10), (10, 10)]);
+}
+
+#[test]
+fn tasks_where_metadata_filters() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "metadata test corpus");
+  let service = mock_service(&backend, "metadata_test_service");
+  for &(entry, metadata) in vec![("/metadata/en.zip", Some(r#"{"language":"en","pages":12}"#)),
+    ("/metadata/de.zip", Some(r#"{"language":"de","pages":3}"#)), ("/metadata/none.zip", None)].iter() {
+    backend.add(Task {
+      id : None,
+      entry : entry.to_string(),
+      serviceid : service.id.unwrap(),
+      corpusid : corpus.id.unwrap(),
+      status : TaskStatus::TODO.raw(),
+      metadata : metadata.map(|text| Json::from_str(text).unwrap())
+    }).unwrap();
+  }
+
+  let english = backend.tasks_where_metadata(&corpus, &service, &Json::from_str(r#"{"language":"en"}"#).unwrap()).unwrap();
+  assert_eq!(english.len(), 1);
+  assert_eq!(english[0].entry, "/metadata/en.zip");
+  assert_eq!(english[0].metadata.as_ref().and_then(|metadata| metadata.find("pages")).and_then(|pages| pages.as_i64()), Some(12));
+  let french = backend.tasks_where_metadata(&corpus, &service, &Json::from_str(r#"{"language":"fr"}"#).unwrap()).unwrap();
+  assert!(french.is_empty());
+}
+
+#[test]
+fn task_metadata_roundtrip() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "metadata roundtrip test corpus");
+  let service = mock_service(&backend, "metadata_roundtrip_test_service");
+  let metadata = Json::from_str(r#"{"language":"en","categories":["math.AG"]}"#).unwrap();
+  let added = backend.add(Task {
+    id : None,
+    entry : "/metadata_roundtrip/added.zip".to_string(),
+    serviceid : service.id.unwrap(),
+    corpusid : corpus.id.unwrap(),
+    status : TaskStatus::TODO.raw(),
+    metadata : Some(metadata.clone())
+  }).unwrap();
+  let selected = added.select_by_key(&backend.connection).unwrap().unwrap();
+  assert_eq!(selected.metadata, Some(metadata.clone()));
+
+  // Imported tasks keep their metadata too
+  let mut imported = Task::imported("/metadata_roundtrip/imported.zip", &corpus);
+  imported.metadata = Some(metadata.clone());
+  backend.mark_imported(&vec![imported.clone()]).unwrap();
+  let selected = imported.select_by_key(&backend.connection).unwrap().unwrap();
+  assert_eq!(selected.metadata, Some(metadata));
+}
+
+#[test]
+fn validate_pipeline_formats() {
+  let backend = mock_backend();
+  let foundation = mock_service(&backend, "foundation_pipeline_test_service");
+  let mismatched_master = mock_service(&backend, "mismatched_pipeline_test_service");
+  let matching_master = backend.add(
+    Service {
+      id : None,
+      name : "matching_pipeline_test_service".to_string(),
+      version : 0.1,
+      inputformat : "html".to_string(),
+      outputformat : "xhtml".to_string(),
+      inputconverter : None,
+      complex : true
+    }).unwrap();
+  for master in vec![&mismatched_master, &matching_master].iter() {
+    backend.connection.execute("INSERT INTO dependencies (master, foundation) values($1, $2)",
+      &[&master.id.unwrap(), &foundation.id.unwrap()]).unwrap();
+  }
+
+  match backend.validate_pipeline() {
+    Err(CortexError::Invalid(message)) => {
+      assert!(message.contains("mismatched_pipeline_test_service"));
+      assert!(!message.contains("matching_pipeline_test_service"));
+    },
+    _ => panic!("a tex consumer of an html producer should be detected")
+  }
+  backend.connection.execute("DELETE FROM dependencies WHERE master = $1", &[&mismatched_master.id.unwrap()]).unwrap();
+  // Only the matching html -> html chain is left
+  let chain = backend.connection.prepare("SELECT count(*) FROM dependencies WHERE foundation = $1").unwrap();
+  let chain_length : i64 = chain.query(&[&foundation.id.unwrap()]).unwrap().get(0).get(0);
+  assert_eq!(chain_length, 1);
+  // (other tests may briefly register their own pipelines concurrently)
+  match backend.validate_pipeline() {
+    Ok(()) => {},
+    Err(CortexError::Invalid(message)) => assert!(!message.contains("foundation_pipeline_test_service")),
+    Err(e) => panic!("unexpected pipeline validation error: {:?}", e)
+  }
+  backend.connection.execute("DELETE FROM dependencies WHERE foundation = $1", &[&foundation.id.unwrap()]).unwrap();
+}
+
+#[test]
+fn validate_pipeline_multiple_formats() {
+  let backend = mock_backend();
+  let foundation = backend.add(
+    Service {
+      id : None,
+      name : "multiformat_foundation_test_service".to_string(),
+      version : 0.1,
+      inputformat : "tex".to_string(),
+      outputformat : "html, xml".to_string(),
+      inputconverter : None,
+      complex : true
+    }).unwrap();
+  let mut masters = Vec::new();
+  for &(name, inputformat) in vec![("multiformat_xml_master_test_service", "xml"),
+    ("multiformat_pdf_master_test_service", "pdf")].iter() {
+    let master = backend.add(
+      Service {
+        id : None,
+        name : name.to_string(),
+        version : 0.1,
+        inputformat : inputformat.to_string(),
+        outputformat : "html".to_string(),
+        inputconverter : None,
+        complex : true
+      }).unwrap();
+    backend.connection.execute("INSERT INTO dependencies (master, foundation) values($1, $2)",
+      &[&master.id.unwrap(), &foundation.id.unwrap()]).unwrap();
+    masters.push(master);
+  }
+
+  match backend.validate_pipeline() {
+    Err(CortexError::Invalid(message)) => {
+      // xml is the second of the foundation's formats, pdf is not produced at all
+      assert!(!message.contains("multiformat_xml_master_test_service"));
+      assert!(message.contains("multiformat_pdf_master_test_service"));
+    },
+    _ => panic!("a pdf consumer of an html and xml producer should be detected")
+  }
+  backend.connection.execute("DELETE FROM dependencies WHERE foundation = $1", &[&foundation.id.unwrap()]).unwrap();
+}
+
+#[test]
+fn approx_task_count_after_analyze() {
+  let mut backend = mock_backend();
+  let corpus = mock_corpus(&backend, "approximate count test corpus");
+  let service = mock_service(&backend, "approximate_count_test_service");
+  for index in 0..20 {
+    let task = mock_task(&backend, &format!("/approximate/{}.zip", index), &service, &corpus, TaskStatus::TODO);
+    backend.mark_done(&vec![mock_report(&task, TaskStatus::Error, vec![("error", "approximate", "count")])]).unwrap();
+  }
+  backend.connection.execute("ANALYZE tasks", &[]).unwrap();
+
+  let count_query = backend.connection.prepare("SELECT count(*) FROM tasks").unwrap();
+  let exact : i64 = count_query.query(&[]).unwrap().get(0).get(0);
+  let approximate = backend.approx_task_count().unwrap();
+  // Other tests may be adding tasks concurrently
+  assert!((approximate - exact).abs() as f64 <= 10.0 + 0.1 * exact as f64,
+    "approximate count {} is too far from the exact {}", approximate, exact);
+
+  backend.approximate_totals = true;
+  let report = backend.task_report(&corpus, &service, Some("error".to_string()), None, None);
+  let total_row = report.iter().find(|row| row.get("name").map(|name| name == "total").unwrap_or(false)).unwrap();
+  let approximate_total = total_row.get("tasks").unwrap().parse::<i64>().unwrap();
+  assert!(approximate_total > 0);
+}
+
+#[test]
+fn staggered_rerun_spreads_availability() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "staggered rerun test corpus");
+  let service = mock_service(&backend, "staggered_rerun_test_service");
+  let mut taskids = Vec::new();
+  for index in 0..5 {
+    let task = mock_task(&backend, &format!("/staggered/{}.zip", index), &service, &corpus, TaskStatus::TODO);
+    taskids.push(task.id.unwrap());
+    backend.mark_done(&vec![mock_report(&task, TaskStatus::Error, vec![("error", "staggered", "rerun")])]).unwrap();
+  }
+
+  // One task every 20 minutes, so only the first is due within the test
+  backend.mark_rerun_selected(&RerunSelector::new(&corpus, &service).severity("error").stagger(Duration::new(1200, 0))).unwrap();
+  let due = backend.peek_tasks(&service, 10).unwrap();
+  assert_eq!(due.iter().map(|task| task.id.unwrap()).collect::<Vec<_>>(), vec![taskids[0]]);
+  let fetched = backend.fetch_tasks(&service, 10, false, None).unwrap();
+  assert_eq!(fetched.len(), 1);
+  assert_eq!(fetched[0].id, Some(taskids[0]));
+
+  // An unstaggered rerun makes everything available at once
+  backend.mark_rerun(&corpus, &service, Some("todo".to_string()), None, None).unwrap();
+  assert_eq!(backend.peek_tasks(&service, 10).unwrap().len(), 4);
+}
+
+#[test]
+fn dashboard_reflects_task_store() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "dashboard test corpus");
+  let service = mock_service(&backend, "dashboard_test_service");
+  mock_task(&backend, "/dashboard/first.zip", &service, &corpus, TaskStatus::TODO);
+  mock_task(&backend, "/dashboard/second.zip", &service, &corpus, TaskStatus::TODO);
+  mock_task(&backend, "/dashboard/third.zip", &service, &corpus, TaskStatus::NoProblem);
+
+  let dashboard = backend.dashboard().unwrap();
+  assert!(dashboard.corpora.iter().any(|c| c.name == "dashboard test corpus"));
+  assert!(dashboard.services.iter().any(|s| s.name == "dashboard_test_service"));
+  assert_eq!(dashboard.queue_depths.get("dashboard_test_service"), Some(&2));
+  assert!(dashboard.total_tasks >= 3);
+  assert!(dashboard.status_percentages.get("todo").unwrap() > &0.0);
+  let percent_sum = dashboard.status_percentages.values().fold(0.0, |sum, percent| sum + percent);
+  assert!((percent_sum - 100.0).abs() < 0.001);
+
+  let encoded = json::encode(&dashboard).unwrap();
+  assert!(encoded.contains("dashboard_test_service"));
+}
+
+#[test]
+fn fetched_entries_are_trimmed() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "entry trim test corpus");
+  let service = mock_service(&backend, "entry_trim_test_service");
+  mock_task(&backend, "/entry_trim/short.zip", &service, &corpus, TaskStatus::TODO);
+
+  let fetched = backend.fetch_tasks(&service, 10, false, None).unwrap();
+  assert_eq!(fetched.len(), 1);
+  assert_eq!(fetched[0].entry, "/entry_trim/short.zip");
+  assert_eq!(fetched[0].entry.trim_right(), fetched[0].entry);
+  assert_eq!(Backend::task_entry_trim("/entry_trim/short.zip    "), "/entry_trim/short.zip");
+}
+
+#[test]
+fn reconnect_after_dropped_connection() {
+  let mut backend = mock_backend();
+  let corpus = mock_corpus(&backend, "reconnect test corpus");
+  let service = mock_service(&backend, "reconnect_test_service");
+  mock_task(&backend, "/reconnect/entry.zip", &service, &corpus, TaskStatus::TODO);
+
+  // Simulate a server-side drop of this connection
+  let _ = backend.connection.execute("SELECT pg_terminate_backend(pg_backend_pid())", &[]);
+  assert!(backend.distinct_entry_count(&corpus).is_err());
+  let count = backend.with_reconnect(|backend| backend.distinct_entry_count(&corpus));
+  assert_eq!(count.unwrap(), 1);
+  // Errors on a live connection are returned without retrying
+  match backend.with_reconnect(|backend| backend.requeue_all(&service, "not the service name")) {
+    Err(CortexError::Invalid(_)) => {},
+    _ => panic!("an invalid requeue should not be retried into success")
+  }
+  assert!(backend.reconnect().is_ok());
+  assert_eq!(backend.distinct_entry_count(&corpus).unwrap(), 1);
+
+  // A dropped replica is reconnected as well, even though the primary is alive
+  let mut replicated_backend = Backend::with_replica(TEST_DB_ADDRESS, TEST_DB_ADDRESS);
+  let _ = replicated_backend.replica.as_ref().unwrap().execute("SELECT pg_terminate_backend(pg_backend_pid())", &[]);
+  assert!(replicated_backend.corpus_breakdown(&service, TaskStatus::TODO).is_err());
+  let breakdown = replicated_backend.with_reconnect(|backend| backend.corpus_breakdown(&service, TaskStatus::TODO)).unwrap();
+  assert_eq!(breakdown.len(), 1);
+  assert_eq!(breakdown[0].1, 1);
+}
+
+#[test]
+fn set_input_converter_in_place() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "input converter test corpus");
+  let service = mock_service(&backend, "input_converter_test_service");
+  let task = mock_task(&backend, "/input_converter/entry.zip", &service, &corpus, TaskStatus::Warning);
+
+  backend.set_input_converter(&service, Some("init")).unwrap();
+  let updated = backend.sync(&service).unwrap();
+  assert_eq!(updated.id, service.id);
+  assert_eq!(updated.inputconverter, Some("init".to_string()));
+  backend.set_input_converter(&service, None).unwrap();
+  assert_eq!(backend.sync(&service).unwrap().inputconverter, None);
+  match backend.set_input_converter(&service, Some("no_such_converter_service")) {
+    Err(CortexError::Invalid(_)) => {},
+    _ => panic!("an unknown converter should be rejected")
+  }
+  assert_eq!(backend.sync(&service).unwrap().inputconverter, None);
+  // A converter with several versions is accepted by name
+  let converter = mock_service(&backend, "input_converter_versioned_test_service");
+  let mut newer_converter = converter.clone();
+  newer_converter.id = None;
+  newer_converter.version = 0.2;
+  backend.add(newer_converter).unwrap();
+  backend.set_input_converter(&service, Some("input_converter_versioned_test_service")).unwrap();
+  assert_eq!(backend.sync(&service).unwrap().inputconverter, Some("input_converter_versioned_test_service".to_string()));
+  backend.set_input_converter(&service, None).unwrap();
+
+  // The tasks of the service are untouched
+  let synced_task = backend.sync(&task).unwrap();
+  assert_eq!(synced_task.id, task.id);
+  assert_eq!(synced_task.status, TaskStatus::Warning.raw());
+}
+
+#[test]
+fn warning_sample_rate_retention() {
+  let mut backend = mock_backend();
+  backend.warning_sample_rate = 0.2;
+  let corpus = mock_corpus(&backend, "warning retention test corpus");
+  let service = mock_service(&backend, "warning_retention_test_service");
+  let task = mock_task(&backend, "/warning_retention/entry.zip", &service, &corpus, TaskStatus::TODO);
+  let mut messages = (0..1000).map(|_| ("warning", "retention", "sampled")).collect::<Vec<_>>();
+  messages.extend((0..10).map(|_| ("error", "retention", "kept")));
+  backend.mark_done(&vec![mock_report(&task, TaskStatus::Error, messages)]).unwrap();
+
+  let count_query = backend.connection.prepare("SELECT count(*) FROM logs WHERE taskid=$1 and severity=$2").unwrap();
+  let warnings : i64 = count_query.query(&[&task.id.unwrap(), &"warning".to_string()]).unwrap().get(0).get(0);
+  let errors : i64 = count_query.query(&[&task.id.unwrap(), &"error".to_string()]).unwrap().get(0).get(0);
+  assert!(warnings > 120 && warnings < 280, "{} warnings kept, expected about 200", warnings);
+  assert_eq!(errors, 10);
+  assert_eq!(backend.sync(&task).unwrap().status, TaskStatus::Error.raw());
+}
+
+#[test]
+fn ensure_system_services_idempotent() {
+  let backend = mock_backend();
+  backend.ensure_system_services().unwrap();
+  backend.ensure_system_services().unwrap();
+
+  let count_query = backend.connection.prepare("SELECT count(*) FROM services WHERE name=$1").unwrap();
+  for &(name, serviceid) in vec![("init", 1), ("import", 2)].iter() {
+    let count : i64 = count_query.query(&[&name.to_string()]).unwrap().get(0).get(0);
+    assert_eq!(count, 1);
+    assert_eq!(Service::from_name(&backend.connection, name.to_string()).unwrap().unwrap().id, Some(serviceid));
+  }
+}
+
+#[test]
+fn entries_missing_service_gaps() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "missing service test corpus");
+  let service = mock_service(&backend, "missing_service_test_service");
+  backend.mark_imported(&vec![Task::imported("/missing_service/covered.zip", &corpus),
+    Task::imported("/missing_service/missed.zip", &corpus)]).unwrap();
+  mock_task(&backend, "/missing_service/covered.zip", &service, &corpus, TaskStatus::TODO);
+
+  assert_eq!(backend.entries_missing_service(&corpus, &service).unwrap(), vec!["/missing_service/missed.zip".to_string()]);
+}
+
+#[test]
+fn import_offline_results() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "offline results test corpus");
+  let service = mock_service(&backend, "offline_results_test_service");
+  let tasks = (0..3).map(|index| mock_task(&backend, &format!("/offline_results/{}.zip", index), &service, &corpus, TaskStatus::TODO))
+    .collect::<Vec<_>>();
+
+  let json_lines = format!("{{\"taskid\": {}, \"status\": \"warning\", \"messages\": [{{\"severity\": \"warning\", \"category\": \"offline\", \"what\": \"json\", \"details\": \"\"}}]}}\n\
+    {{\"taskid\": {}, \"status\": \"no_problem\"}}\n", tasks[0].id.unwrap(), tasks[1].id.unwrap());
+  assert_eq!(backend.import_results(&mut json_lines.as_bytes(), ResultFormat::JsonLines).unwrap(), 2);
+  // Results for tasks missing from the Task store are skipped
+  let csv = format!("taskid,status,severity,category,what\n{0},error,error,offline,csv\n{0},error,warning,offline,csv\n-1,fatal,,,\n",
+    tasks[2].id.unwrap());
+  assert_eq!(backend.import_results(&mut csv.as_bytes(), ResultFormat::Csv).unwrap(), 1);
+
+  assert_eq!(backend.sync(&tasks[0]).unwrap().status, TaskStatus::Warning.raw());
+  assert_eq!(backend.sync(&tasks[1]).unwrap().status, TaskStatus::NoProblem.raw());
+  assert_eq!(backend.sync(&tasks[2]).unwrap().status, TaskStatus::Error.raw());
+  let count_query = backend.connection.prepare("SELECT count(*) FROM logs WHERE taskid=$1").unwrap();
+  let csv_messages : i64 = count_query.query(&[&tasks[2].id.unwrap()]).unwrap().get(0).get(0);
+  assert_eq!(csv_messages, 2);
+
+  match backend.import_results(&mut "not a result\n".as_bytes(), ResultFormat::JsonLines) {
+    Err(CortexError::Invalid(_)) => {},
+    _ => panic!("a malformed results file should be rejected")
+  }
+}
+
+#[test]
+fn queue_position_follows_fetch_order() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "queue position test corpus");
+  let service = mock_service(&backend, "queue_position_test_service");
+  let tasks = (0..4).map(|index| mock_task(&backend, &format!("/queue_position/{}.zip", index), &service, &corpus, TaskStatus::TODO))
+    .collect::<Vec<_>>();
+  let done = mock_task(&backend, "/queue_position/done.zip", &service, &corpus, TaskStatus::NoProblem);
+  // The last task jumps the line
+  backend.connection.execute("UPDATE tasks SET priority=1 WHERE taskid=$1", &[&tasks[3].id.unwrap()]).unwrap();
+
+  let fetch_order = backend.peek_tasks(&service, 10).unwrap();
+  assert_eq!(fetch_order.len(), 4);
+  for (position, task) in fetch_order.iter().enumerate() {
+    assert_eq!(backend.queue_position(task.id.unwrap()).unwrap(), Some(position as i64));
+  }
+  assert_eq!(backend.queue_position(tasks[3].id.unwrap()).unwrap(), Some(0));
+  assert_eq!(backend.queue_position(done.id.unwrap()).unwrap(), None);
+}
+
+#[test]
+fn set_corpus_path_relocates_tasks() {
+  for &relative_entries in vec![false, true].iter() {
+    let mut backend = mock_backend();
+    backend.relative_entries = relative_entries;
+    let old_root = format!("/mnt/old_root/moving_corpus_{}", relative_entries);
+    let new_root = format!("/mnt/new_root/moving_corpus_{}", relative_entries);
+    let corpus = backend.add(
+      Corpus {
+        id : None,
+        name : old_root.clone(),
+        path : old_root.clone(),
+        complex : true
+      }).unwrap();
+    let service = mock_service(&backend, &format!("moving_corpus_test_service_{}", relative_entries));
+    backend.mark_imported(&vec![Task::imported(&(old_root.clone() + "/1501.0002/1501.0002.zip"), &corpus)]).unwrap();
+    assert!(backend.register_service(service.clone(), old_root.clone()).is_ok());
+
+    backend.set_corpus_path(&corpus, &new_root).unwrap();
+    let moved = Corpus::from_name(&backend.connection, old_root.clone()).unwrap().unwrap();
+    assert_eq!(moved.id, corpus.id);
+    assert_eq!(moved.path, new_root);
+    let dispatched = backend.fetch_tasks(&service, 10, false, None).unwrap();
+    assert_eq!(dispatched.len(), 1);
+    assert_eq!(dispatched[0].entry, new_root.clone() + "/1501.0002/1501.0002.zip");
+  }
+}
+
+#[test]
+fn rerun_selector_combinations() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "rerun selector test corpus");
+  let service = mock_service(&backend, "rerun_selector_test_service");
+
+  match RerunSelector::new(&corpus, &service).category("selector") {
+    Err(CortexError::Invalid(_)) => {},
+    _ => panic!("a category without a severity should be rejected")
+  }
+  match RerunSelector::new(&corpus, &service).severity("error").what("unscoped") {
+    Err(CortexError::Invalid(_)) => {},
+    _ => panic!("a what without a category should be rejected")
+  }
+
+  let selected = mock_task(&backend, "/rerun_selector/selected.zip", &service, &corpus, TaskStatus::TODO);
+  let other = mock_task(&backend, "/rerun_selector/other.zip", &service, &corpus, TaskStatus::TODO);
+  backend.mark_done(&vec![mock_report(&selected, TaskStatus::Error, vec![("error", "selector", "chosen")]),
+    mock_report(&other, TaskStatus::Error, vec![("error", "selector", "ignored")])]).unwrap();
+  let selector = RerunSelector::new(&corpus, &service).severity("error").category("selector").unwrap().what("chosen").unwrap();
+  backend.mark_rerun_selected(&selector).unwrap();
+  assert_eq!(backend.sync(&selected).unwrap().status, TaskStatus::TODO.raw());
+  assert_eq!(backend.sync(&other).unwrap().status, TaskStatus::Error.raw());
+}
+
+#[test]
+fn concurrent_register_service_without_duplicates() {
+  let backend = mock_backend();
+  let import_service = Service::from_name(&backend.connection, "import".to_string()).unwrap().unwrap();
+  let corpus = backend.add(
+    Corpus {
+      id : None,
+      name : "/register/concurrent/".to_string(),
+      path : "/register/concurrent/".to_string(),
+      complex : true
+    }).unwrap();
+  let service = mock_service(&backend, "register_concurrent_test_service");
+  for index in 0..50 {
+    mock_task(&backend, &format!("/register/concurrent/{0}/{0}.zip", index), &import_service, &corpus, TaskStatus::NoProblem);
+  }
+
+  let registrations = (0..2).map(|_| {
+    let thread_service = service.clone();
+    let thread_path = corpus.path.clone();
+    thread::spawn(move || {
+      let thread_backend = Backend::testdb();
+      thread_backend.register_service(thread_service, thread_path).is_ok()
+    })
+  }).collect::<Vec<_>>();
+  for registration in registrations.into_iter() {
+    assert!(registration.join().unwrap());
+  }
+
+  let count_query = backend.connection.prepare(
+    "SELECT count(*), count(DISTINCT entry) FROM tasks WHERE serviceid=$1 and corpusid=$2").unwrap();
+  let counts = count_query.query(&[&service.id.unwrap(), &corpus.id.unwrap()]).unwrap();
+  let total : i64 = counts.get(0).get(0);
+  let distinct : i64 = counts.get(0).get(1);
+  assert_eq!(total, 50);
+  assert_eq!(distinct, 50);
+}
+
+#[test]
+fn progress_snapshot_deltas() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "progress snapshot test corpus");
+  let service = mock_service(&backend, "progress_snapshot_test_service");
+  let tasks = (0..5).map(|index| mock_task(&backend, &format!("/progress_snapshot/{}.zip", index), &service, &corpus, TaskStatus::TODO))
+    .collect::<Vec<_>>();
+  let before = backend.snapshot_progress(&corpus, &service);
+  assert_eq!(before.counts.get("todo"), Some(&5));
+
+  backend.mark_done(&vec![mock_report(&tasks[0], TaskStatus::NoProblem, Vec::new()),
+    mock_report(&tasks[1], TaskStatus::NoProblem, Vec::new()),
+    mock_report(&tasks[2], TaskStatus::Error, vec![("error", "snapshot", "delta")])]).unwrap();
+  let after = backend.snapshot_progress(&corpus, &service);
+
+  let delta = Backend::compare_progress(&before, &after);
+  assert!(delta.elapsed >= 0);
+  assert_eq!(delta.deltas.get("no_problem"), Some(&2));
+  assert_eq!(delta.deltas.get("error"), Some(&1));
+  assert_eq!(delta.deltas.get("todo"), Some(&-3));
+  assert_eq!(delta.deltas.get("total"), Some(&0));
+}
+
+#[test]
+fn rerun_refused_over_max_tasks() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "rerun limit test corpus");
+  let service = mock_service(&backend, "rerun_limit_test_service");
+  let tasks = (0..5).map(|index| mock_task(&backend, &format!("/rerun_limit/{}.zip", index), &service, &corpus, TaskStatus::TODO))
+    .collect::<Vec<_>>();
+  backend.mark_done(&tasks.iter().map(|task| mock_report(task, TaskStatus::Error, vec![("error", "limit", "broad")]))
+    .collect::<Vec<_>>()).unwrap();
+
+  match backend.mark_rerun_selected(&RerunSelector::new(&corpus, &service).severity("error").max_tasks(3)) {
+    Err(CortexError::Invalid(_)) => {},
+    _ => panic!("a rerun of 5 tasks should be refused with a limit of 3")
+  }
+  for task in tasks.iter() {
+    assert_eq!(backend.sync(task).unwrap().status, TaskStatus::Error.raw());
+  }
+  assert!(!backend.top_whats(&service, "error", 10).unwrap().is_empty());
+
+  backend.mark_rerun_selected(&RerunSelector::new(&corpus, &service).severity("error").max_tasks(5)).unwrap();
+  for task in tasks.iter() {
+    assert_eq!(backend.sync(task).unwrap().status, TaskStatus::TODO.raw());
+  }
+}
+
+#[test]
+fn report_statements_prepared_once() {
+  let mut backend = mock_backend();
+  let corpus = mock_corpus(&backend, "prepared statements test corpus");
+  let service = mock_service(&backend, "prepared_statements_test_service");
+  let task = mock_task(&backend, "/prepared_statements/entry.zip", &service, &corpus, TaskStatus::TODO);
+  backend.mark_done(&vec![mock_report(&task, TaskStatus::Error, vec![("error", "prepared", "once")])]).unwrap();
+  let prepared_count = |backend : &Backend| -> i64 {
+    backend.connection.prepare("SELECT count(*) FROM pg_prepared_statements").unwrap()
+      .query(&[]).unwrap().get(0).get(0)
+  };
+
+  let first_report = backend.task_report(&corpus, &service, Some("error".to_string()), None, None);
+  let first_progress = backend.progress_report(&corpus, &service);
+  let prepared = prepared_count(&backend);
+  for _ in 0..3 {
+    assert_eq!(backend.task_report(&corpus, &service, Some("error".to_string()), None, None), first_report);
+    assert_eq!(backend.progress_report(&corpus, &service), first_progress);
+  }
+  // Repeated reports reuse their statements, rather than preparing new ones
+  assert_eq!(prepared_count(&backend), prepared);
+
+  // A fresh connection starts over
+  backend.reconnect().unwrap();
+  assert!(prepared_count(&backend) < prepared);
+}
+
+#[test]
+fn fetch_tasks_grouped_by_entry() {
+  let backend = mock_backend();
+  let first_corpus = mock_corpus(&backend, "grouped fetch first test corpus");
+  let second_corpus = mock_corpus(&backend, "grouped fetch second test corpus");
+  let service = mock_service(&backend, "grouped_fetch_test_service");
+  let first_a = mock_task(&backend, "/grouped_fetch/a.zip", &service, &first_corpus, TaskStatus::TODO);
+  let first_b = mock_task(&backend, "/grouped_fetch/b.zip", &service, &first_corpus, TaskStatus::TODO);
+  let second_b = mock_task(&backend, "/grouped_fetch/b.zip", &service, &second_corpus, TaskStatus::TODO);
+  let second_a = mock_task(&backend, "/grouped_fetch/a.zip", &service, &second_corpus, TaskStatus::TODO);
+
+  // In plain taskid order, the first batch of two would hold two different entries
+  let peeked = backend.peek_tasks(&service, 2).unwrap();
+  assert_eq!(peeked.iter().map(|task| task.id).collect::<Vec<_>>(), vec![first_a.id, first_b.id]);
+
+  let grouped = backend.fetch_tasks(&service, 2, true, None).unwrap();
+  assert_eq!(grouped.iter().map(|task| task.id).collect::<Vec<_>>(), vec![first_a.id, second_a.id]);
+  let rest = backend.fetch_tasks(&service, 2, true, None).unwrap();
+  assert_eq!(rest.iter().map(|task| task.id).collect::<Vec<_>>(), vec![first_b.id, second_b.id]);
+}
+
+#[test]
+fn fetch_tasks_grouped_across_services() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "grouped services fetch test corpus");
+  let service = mock_service(&backend, "grouped_services_fetch_test_service");
+  let other_service = mock_service(&backend, "grouped_services_fetch_other_service");
+  let first = mock_task(&backend, "/grouped_services/first.zip", &service, &corpus, TaskStatus::TODO);
+  let second = mock_task(&backend, "/grouped_services/second.zip", &service, &corpus, TaskStatus::TODO);
+  let other_second = mock_task(&backend, "/grouped_services/second.zip", &other_service, &corpus, TaskStatus::TODO);
+  let other_only = mock_task(&backend, "/grouped_services/other_only.zip", &other_service, &corpus, TaskStatus::TODO);
+  backend.connection.execute("UPDATE tasks SET priority = 5 WHERE taskid = $1", &[&second.id.unwrap()]).unwrap();
+
+  // The prioritized entry comes first, with the tasks of both services, while entries
+  // without a task of the requested service are left alone
+  let grouped = backend.fetch_tasks(&service, 10, true, None).unwrap();
+  assert_eq!(grouped.iter().map(|task| task.id).collect::<Vec<_>>(), vec![second.id, other_second.id, first.id]);
+  assert_eq!(backend.sync(&other_only).unwrap().status, TaskStatus::TODO.raw());
+}
+
+#[test]
+fn delete_task_with_logs() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "delete task test corpus");
+  let service = mock_service(&backend, "delete_task_test_service");
+  let task = mock_task(&backend, "/delete_task/corrupt.zip", &service, &corpus, TaskStatus::TODO);
+  let kept_task = mock_task(&backend, "/delete_task/kept.zip", &service, &corpus, TaskStatus::TODO);
+  backend.mark_done(&vec![
+    mock_report(&task, TaskStatus::Error, vec![("error", "delete_task", "corrupt")]),
+    mock_report(&kept_task, TaskStatus::Error, vec![("error", "delete_task", "kept")])
+  ]).unwrap();
+
+  assert!(backend.delete_task(task.id.unwrap()).is_ok());
+  let task_count : i64 = backend.connection.prepare("SELECT count(*) FROM tasks WHERE taskid = $1").unwrap()
+    .query(&[&task.id.unwrap()]).unwrap().get(0).get(0);
+  assert_eq!(task_count, 0);
+  let log_query = backend.connection.prepare("SELECT count(*) FROM logs WHERE taskid = $1").unwrap();
+  let log_count : i64 = log_query.query(&[&task.id.unwrap()]).unwrap().get(0).get(0);
+  assert_eq!(log_count, 0);
+  // Other tasks of the same corpus and service are untouched
+  let kept_log_count : i64 = log_query.query(&[&kept_task.id.unwrap()]).unwrap().get(0).get(0);
+  assert_eq!(kept_log_count, 1);
+  assert_eq!(backend.sync(&kept_task).unwrap().status, TaskStatus::Error.raw());
+
+  match backend.delete_task(task.id.unwrap()) {
+    Err(CortexError::Invalid(_)) => {},
+    _ => panic!("a deleted task should not be deletable again")
+  }
+}
+
+#[test]
+fn reports_read_from_replica() {
+  let mut backend = Backend::with_replica(TEST_DB_ADDRESS, TEST_DB_ADDRESS);
+  if backend.needs_init().unwrap() {
+    backend.setup_task_tables().unwrap();
+  }
+  let corpus = mock_corpus(&backend, "replica test corpus");
+  let service = mock_service(&backend, "replica_test_service");
+  let task = mock_task(&backend, "/replica/entry.zip", &service, &corpus, TaskStatus::TODO);
+  // Shadow the tasks table in the replica session, so that it diverges from the primary
+  {
+    let replica = backend.replica.as_ref().unwrap();
+    replica.execute("CREATE TEMPORARY TABLE tasks (LIKE tasks INCLUDING ALL)", &[]).unwrap();
+    replica.execute("INSERT INTO tasks (entry, serviceid, corpusid, status) values ($1, $2, $3, $4)",
+      &[&"/replica/entry.zip", &service.id.unwrap(), &corpus.id.unwrap(), &TaskStatus::Error.raw()]).unwrap();
+  }
+
+  let report = backend.progress_report(&corpus, &service);
+  assert_eq!(report.get("error"), Some(&1.0));
+  assert_eq!(report.get("todo"), Some(&0.0));
+  assert!(backend.corpora().iter().any(|replica_corpus| replica_corpus.name == "replica test corpus"));
+  assert!(backend.services().iter().any(|replica_service| replica_service.name == "replica_test_service"));
+
+  // Writes go to the primary, leaving the replica's view untouched
+  backend.mark_done(&vec![mock_report(&task, TaskStatus::Warning, vec![("warning", "replica", "primary")])]).unwrap();
+  let primary_status : i32 = backend.connection.prepare("SELECT status FROM tasks WHERE taskid = $1").unwrap()
+    .query(&[&task.id.unwrap()]).unwrap().get(0).get(0);
+  assert_eq!(primary_status, TaskStatus::Warning.raw());
+  assert_eq!(backend.progress_report(&corpus, &service).get("warning"), Some(&0.0));
+
+  // Reconnecting drops the shadowing session, after which the replica catches up
+  backend.reconnect().unwrap();
+  assert_eq!(backend.progress_report(&corpus, &service).get("warning"), Some(&1.0));
+}
+
+#[test]
+fn corpus_breakdown_orders_by_count() {
+  let backend = mock_backend();
+  let service = mock_service(&backend, "corpus_breakdown_test_service");
+  let quiet_corpus = mock_corpus(&backend, "corpus breakdown quiet test corpus");
+  let noisy_corpus = mock_corpus(&backend, "corpus breakdown noisy test corpus");
+  let clean_corpus = mock_corpus(&backend, "corpus breakdown clean test corpus");
+  mock_task(&backend, "/corpus_breakdown/quiet_1.zip", &service, &quiet_corpus, TaskStatus::Error);
+  mock_task(&backend, "/corpus_breakdown/quiet_2.zip", &service, &quiet_corpus, TaskStatus::NoProblem);
+  for index in 0..3 {
+    mock_task(&backend, &format!("/corpus_breakdown/noisy_{}.zip", index), &service, &noisy_corpus, TaskStatus::Error);
+  }
+  mock_task(&backend, "/corpus_breakdown/clean.zip", &service, &clean_corpus, TaskStatus::NoProblem);
+
+  let breakdown = backend.corpus_breakdown(&service, TaskStatus::Error).unwrap();
+  let named_counts = breakdown.iter().map(|&(ref corpus, count)| (corpus.name.clone(), count)).collect::<Vec<_>>();
+  assert_eq!(named_counts, vec![
+    ("corpus breakdown noisy test corpus".to_string(), 3),
+    ("corpus breakdown quiet test corpus".to_string(), 1)]);
+  assert_eq!(breakdown[0].0.id, noisy_corpus.id);
+}
+
+#[test]
+fn tagged_tasks_fetch_and_report() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "task tags test corpus");
+  let service = mock_service(&backend, "task_tags_test_service");
+  let mut taskids = Vec::new();
+  for index in 0..4 {
+    let task = mock_task(&backend, &format!("/task_tags/{}.zip", index), &service, &corpus, TaskStatus::TODO);
+    taskids.push(task.id.unwrap());
+  }
+  let sample = vec![taskids[1], taskids[3]];
+  assert_eq!(backend.tag_tasks(&sample, "reviewer sample").unwrap(), 2);
+  // Tagging is idempotent
+  assert_eq!(backend.tag_tasks(&sample, "reviewer sample").unwrap(), 0);
+
+  let tagged_report = backend.progress_report_tagged(&corpus, &service, "reviewer sample");
+  assert_eq!(tagged_report.get("todo"), Some(&2.0));
+  assert_eq!(tagged_report.get("total"), Some(&2.0));
+  let fetched = backend.fetch_tasks(&service, 10, false, Some("reviewer sample")).unwrap();
+  assert_eq!(fetched.iter().map(|task| task.id.unwrap()).collect::<Vec<_>>(), sample);
+  assert!(backend.fetch_tasks(&service, 10, false, Some("unknown sample")).unwrap().is_empty());
+
+  // Untagged tasks are no longer selected by the tag
+  backend.mark_done(&vec![mock_report(&fetched[0], TaskStatus::NoProblem, vec![])]).unwrap();
+  assert_eq!(backend.untag_tasks(&[taskids[3]], "reviewer sample").unwrap(), 1);
+  let untagged_report = backend.progress_report_tagged(&corpus, &service, "reviewer sample");
+  assert_eq!(untagged_report.get("no_problem"), Some(&1.0));
+  assert_eq!(untagged_report.get("total"), Some(&1.0));
+  assert_eq!(backend.fetch_tasks(&service, 10, false, None).unwrap().len(), 2);
+}
+
+#[test]
+fn compare_services_classifies_entries() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "compare services test corpus");
+  let old_service = mock_service(&backend, "compare_services_old_test_service");
+  let new_service = mock_service(&backend, "compare_services_new_test_service");
+  for (entry, old_status, new_status) in vec![
+    ("/compare_services/regressed.zip", TaskStatus::NoProblem, TaskStatus::Error),
+    ("/compare_services/improved.zip", TaskStatus::Fatal, TaskStatus::Warning),
+    ("/compare_services/unchanged.zip", TaskStatus::Warning, TaskStatus::Warning),
+    ("/compare_services/pending.zip", TaskStatus::NoProblem, TaskStatus::TODO)].into_iter() {
+    mock_task(&backend, entry, &old_service, &corpus, old_status);
+    mock_task(&backend, entry, &new_service, &corpus, new_status);
+  }
+  // Entries only processed by one of the services are not compared
+  mock_task(&backend, "/compare_services/old_only.zip", &old_service, &corpus, TaskStatus::Error);
+
+  let diff = backend.compare_services(&corpus, &old_service, &new_service).unwrap();
+  assert_eq!(diff, ServiceDiff {
+    regressed : vec!["/compare_services/regressed.zip".to_string()],
+    improved : vec!["/compare_services/improved.zip".to_string()],
+    unchanged : vec!["/compare_services/unchanged.zip".to_string()]
+  });
+  // The comparison is directional
+  let reverse_diff = backend.compare_services(&corpus, &new_service, &old_service).unwrap();
+  assert_eq!(reverse_diff.regressed, diff.improved);
+  assert_eq!(reverse_diff.improved, diff.regressed);
+}
+
+#[test]
+fn rerun_curated_entries() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "rerun entries test corpus");
+  let service = mock_service(&backend, "rerun_entries_test_service");
+  let picked_task = mock_task(&backend, "/rerun_entries/picked.zip", &service, &corpus, TaskStatus::TODO);
+  let other_task = mock_task(&backend, "/rerun_entries/other.zip", &service, &corpus, TaskStatus::TODO);
+  backend.mark_done(&vec![
+    mock_report(&picked_task, TaskStatus::Error, vec![("error", "rerun_entries", "picked")]),
+    mock_report(&other_task, TaskStatus::Error, vec![("error", "rerun_entries", "other")])
+  ]).unwrap();
+
+  // A list as read from a text file, with stray whitespace and an unknown entry
+  let curated = vec!["/rerun_entries/picked.zip \n".to_string(), "/rerun_entries/missing.zip".to_string()];
+  assert_eq!(backend.rerun_entries(&corpus, &service, &curated).unwrap(), 1);
+  assert_eq!(backend.sync(&picked_task).unwrap().status, TaskStatus::TODO.raw());
+  assert_eq!(backend.sync(&other_task).unwrap().status, TaskStatus::Error.raw());
+  let log_query = backend.connection.prepare("SELECT count(*) FROM logs WHERE taskid = $1").unwrap();
+  let picked_logs : i64 = log_query.query(&[&picked_task.id.unwrap()]).unwrap().get(0).get(0);
+  assert_eq!(picked_logs, 0);
+  let other_logs : i64 = log_query.query(&[&other_task.id.unwrap()]).unwrap().get(0).get(0);
+  assert_eq!(other_logs, 1);
+}
+
+#[test]
+fn entry_overview_across_services() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "entry overview test corpus");
+  let first_service = mock_service(&backend, "entry_overview_first_test_service");
+  let second_service = mock_service(&backend, "entry_overview_second_test_service");
+  mock_task(&backend, "/entry_overview/paper.zip", &first_service, &corpus, TaskStatus::NoProblem);
+  mock_task(&backend, "/entry_overview/paper.zip", &second_service, &corpus, TaskStatus::Error);
+  mock_task(&backend, "/entry_overview/other.zip", &second_service, &corpus, TaskStatus::Fatal);
+
+  let overview = backend.entry_overview(" /entry_overview/paper.zip ").unwrap();
+  let rows = overview.iter().map(|&(ref row_corpus, ref row_service, ref status)|
+    (row_corpus.name.clone(), row_service.name.clone(), status.raw())).collect::<Vec<_>>();
+  assert_eq!(rows, vec![
+    ("entry overview test corpus".to_string(), "entry_overview_first_test_service".to_string(), TaskStatus::NoProblem.raw()),
+    ("entry overview test corpus".to_string(), "entry_overview_second_test_service".to_string(), TaskStatus::Error.raw())]);
+  assert!(backend.entry_overview("/entry_overview/missing.zip").unwrap().is_empty());
+}
+
+#[test]
+fn mark_imported_reports_bad_entries() {
+  let mut backend = mock_backend();
+  let corpus = mock_corpus(&backend, "partial import test corpus");
+  let service = mock_service(&backend, "partial_import_test_service");
+  // Entries are stored in a fixed-width column, so an over-long one violates it
+  let bad_entry = format!("/partial_import/{}.zip", "x".repeat(250));
+  let mut tasks = (0..5).map(|index| Task {
+    id : None,
+    entry : format!("/partial_import/{}.zip", index),
+    serviceid : service.id.unwrap(),
+    corpusid : corpus.id.unwrap(),
+    status : TaskStatus::TODO.raw(),
+    metadata : None
+  }).collect::<Vec<_>>();
+  let mut bad_task = tasks[0].clone();
+  bad_task.entry = bad_entry.clone();
+  tasks.insert(2, bad_task);
+  let count_query = backend.connection.prepare("SELECT count(*) FROM tasks WHERE corpusid=$1 and serviceid=$2").unwrap();
+
+  // By default, the whole import is aborted, naming the offending entry
+  match backend.mark_imported(&tasks) {
+    Err(CortexError::Invalid(message)) => assert!(message.contains(&bad_entry)),
+    _ => panic!("an over-long entry should abort the import")
+  }
+  let count : i64 = count_query.query(&[&corpus.id.unwrap(), &service.id.unwrap()]).unwrap().get(0).get(0);
+  assert_eq!(count, 0);
+
+  backend.import_skip_errors = true;
+  assert_eq!(backend.mark_imported(&tasks).unwrap(), vec![bad_entry]);
+  let count : i64 = count_query.query(&[&corpus.id.unwrap(), &service.id.unwrap()]).unwrap().get(0).get(0);
+  assert_eq!(count, 5);
+}
+
+#[test]
+fn service_and_corpus_exist() {
+  let backend = mock_backend();
+  mock_corpus(&backend, "exists test corpus");
+  mock_service(&backend, "exists_test_service");
+
+  assert!(backend.service_exists("exists_test_service", None).unwrap());
+  assert!(backend.service_exists("exists_test_service", Some(0.1)).unwrap());
+  assert!(!backend.service_exists("exists_test_service", Some(0.2)).unwrap());
+  assert!(!backend.service_exists("absent_exists_test_service", None).unwrap());
+  assert!(backend.corpus_exists("exists test corpus").unwrap());
+  assert!(!backend.corpus_exists("absent exists test corpus").unwrap());
+}
+
+#[test]
+fn import_complete_once_import_tasks_done() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "import complete test corpus");
+  let import_service = Service::from_name(&backend.connection, "import".to_string()).unwrap().unwrap();
+  mock_task(&backend, "/import_complete/done.zip", &import_service, &corpus, TaskStatus::NoProblem);
+  let pending_task = mock_task(&backend, "/import_complete/pending.zip", &import_service, &corpus, TaskStatus::TODO);
+  assert!(!backend.import_complete(&corpus).unwrap());
+
+  // In progress import tasks also keep the import incomplete
+  let claimed_task = backend.claim_task(pending_task.id.unwrap()).unwrap().unwrap();
+  assert!(!backend.import_complete(&corpus).unwrap());
+  backend.mark_done(&vec![mock_report(&claimed_task, TaskStatus::NoProblem, vec![])]).unwrap();
+  assert!(backend.import_complete(&corpus).unwrap());
+}
+
+#[test]
+fn fetch_dispatched_bundles_service() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "fetch dispatched test corpus");
+  let service = mock_service(&backend, "fetch_dispatched_test_service");
+  let fresh_task = mock_task(&backend, "/fetch_dispatched/fresh.zip", &service, &corpus, TaskStatus::TODO);
+  let rerun_task = mock_task(&backend, "/fetch_dispatched/rerun.zip", &service, &corpus, TaskStatus::Error);
+  backend.rerun_tasks(&[rerun_task.id.unwrap()]).unwrap();
+
+  let dispatched : Vec<DispatchedTask> = backend.fetch_dispatched(&service, 10, false, None).unwrap();
+  assert_eq!(dispatched.len(), 2);
+  for bundle in dispatched.iter() {
+    assert_eq!(bundle.service.id, Some(bundle.task.serviceid));
+    assert_eq!(bundle.service.name, "fetch_dispatched_test_service");
+    assert_eq!(bundle.service.inputconverter, Some("import".to_string()));
+    let expected_attempt = if bundle.task.id == fresh_task.id { 1 } else { 2 };
+    assert_eq!(bundle.attempt, expected_attempt);
+  }
+  // The tasks are claimed, just as by fetch_tasks
+  assert!(backend.fetch_dispatched(&service, 10, false, None).unwrap().is_empty());
+
+  // The fetch_tasks options carry over, e.g. tagged selections
+  let untagged_task = mock_task(&backend, "/fetch_dispatched/untagged.zip", &service, &corpus, TaskStatus::TODO);
+  let tagged_task = mock_task(&backend, "/fetch_dispatched/tagged.zip", &service, &corpus, TaskStatus::TODO);
+  backend.tag_tasks(&[tagged_task.id.unwrap()], "fetch_dispatched_test_tag").unwrap();
+  let tagged = backend.fetch_dispatched(&service, 10, false, Some("fetch_dispatched_test_tag")).unwrap();
+  assert_eq!(tagged.iter().map(|bundle| bundle.task.id).collect::<Vec<_>>(), vec![tagged_task.id]);
+  assert_eq!(backend.sync(&untagged_task).unwrap().status, TaskStatus::TODO.raw());
+}
+
+#[test]
+fn what_report_lists_examples() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "what examples test corpus");
+  let service = mock_service(&backend, "what_examples_test_service");
+  let mut reports = Vec::new();
+  for &(entry, what) in vec![("/what_examples/1206.5501.zip", "undefined"), ("/what_examples/1508.01222.zip", "undefined"),
+    ("/what_examples/1501.0001.zip", "missing_file")].iter() {
+    let task = mock_task(&backend, entry, &service, &corpus, TaskStatus::TODO);
+    reports.push(mock_report(&task, TaskStatus::Error, vec![("error", "what_examples", what)]));
+  }
+  backend.mark_done(&reports).unwrap();
+
+  let report = backend.task_report(&corpus, &service, Some("error".to_string()), Some("what_examples".to_string()), None);
+  let examples_of = |what : &str| report.iter().find(|row| row.get("name") == Some(&what.to_string()))
+    .and_then(|row| row.get("examples").cloned());
+  assert_eq!(examples_of("undefined"), Some("1206.5501, 1508.01222".to_string()));
+  assert_eq!(examples_of("missing_file"), Some("1501.0001".to_string()));
+
+  // The streamed report carries the same examples
+  let mut streamed = Vec::new();
+  backend.task_report_stream(&corpus, &service, Some("error".to_string()), Some("what_examples".to_string()), None,
+    |row| streamed.push(row)).unwrap();
+  assert_eq!(streamed, report);
+}
+
+#[test]
+fn typed_progress_report() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "typed progress test corpus");
+  let service = mock_service(&backend, "typed_progress_test_service");
+  for (index, status) in vec![TaskStatus::NoProblem, TaskStatus::Warning, TaskStatus::Warning,
+    TaskStatus::Error, TaskStatus::Fatal, TaskStatus::TODO].into_iter().enumerate() {
+    mock_task(&backend, &format!("/typed_progress/{}.zip", index), &service, &corpus, status);
+  }
+
+  let report = backend.progress_report_typed(&corpus, &service);
+  assert_eq!(report["warning"], StatusProgress { count : 2, percent : 33.33 });
+  assert_eq!(report["total"], StatusProgress { count : 6, percent : 100.0 });
+  assert_eq!(report["cancelled"].count, 0);
+  let percent_sum = report.iter().filter(|&(key, _)| key != "total").map(|(_, progress)| progress.percent).fold(0.0, |sum, percent| sum + percent);
+  assert!((percent_sum - 100.0).abs() < 0.1);
+  let count_sum = report.iter().filter(|&(key, _)| key != "total").map(|(_, progress)| progress.count).fold(0, |sum, count| sum + count);
+  assert_eq!(count_sum, 6);
+}
+
+#[test]
+fn soft_deleted_corpus_hidden_until_purged() {
+  let backend = mock_backend();
+  let corpus = mock_corpus(&backend, "soft delete test corpus");
+  let service = mock_service(&backend, "soft_delete_test_service");
+  let task = mock_task(&backend, "/soft_delete/entry.zip", &service, &corpus, TaskStatus::TODO);
+  backend.mark_done(&vec![mock_report(&task, TaskStatus::Error, vec![("error", "soft_delete", "kept")])]).unwrap();
+  let other_service = mock_service(&backend, "soft_delete_other_test_service");
+  let queued_task = mock_task(&backend, "/soft_delete/entry.zip", &other_service, &corpus, TaskStatus::TODO);
+
+  backend.delete_corpus(&corpus).unwrap();
+  // The queued tasks of a soft-deleted corpus are no longer dispatched, for any service
+  assert!(backend.peek_tasks(&other_service, 10).unwrap().is_empty());
+  assert_eq!(backend.queue_position(queued_task.id.unwrap()).unwrap(), None);
+  assert!(backend.fetch_tasks(&other_service, 10, false, None).unwrap().is_empty());
+  assert!(backend.fetch_tasks(&other_service, 10, true, None).unwrap().is_empty());
+  assert!(backend.fetch_dispatched(&other_service, 10, false, None).unwrap().is_empty());
+  assert_eq!(backend.sync(&queued_task).unwrap().status, TaskStatus::TODO.raw());
+  backend.delete_service(&service).unwrap();
+  assert!(!backend.corpora().iter().any(|listed| listed.id == corpus.id));
+  assert!(!backend.services().iter().any(|listed| listed.id == service.id));
+  // Nor found by name
+  assert!(!backend.corpus_exists("soft delete test corpus").unwrap());
+  assert!(Corpus::from_name(&backend.connection, "soft delete test corpus".to_string()).unwrap().is_none());
+  assert!(!backend.service_exists("soft_delete_test_service", None).unwrap());
+  assert!(Service::from_name(&backend.connection, "soft_delete_test_service".to_string()).unwrap().is_none());
+  assert!(!backend.latest_versions().unwrap().contains_key("soft_delete_test_service"));
+  assert!(!backend.dashboard().unwrap().queue_depths.contains_key("soft_delete_test_service"));
+  match backend.set_service_enabled(&service, true) {
+    Err(CortexError::Invalid(_)) => {},
+    other => panic!("Expected an Invalid error, got {:?}", other)
+  }
+  // Still queryable by id, with its tasks and logs intact
+  let by_id = Corpus { id : corpus.id, name : String::new(), path : String::new(), complex : false };
+  assert_eq!(backend.sync(&by_id).unwrap().name, "soft delete test corpus");
+  assert_eq!(backend.sync(&task).unwrap().status, TaskStatus::Error.raw());
+  // Soft-deleted services are no longer dispatched
+  backend.rerun_tasks(&[task.id.unwrap()]).unwrap();
+  assert!(backend.fetch_tasks(&service, 10, false, None).unwrap().is_empty());
+
+  backend.purge_deleted().unwrap();
+  let corpus_count : i64 = backend.connection.prepare("SELECT count(*) FROM corpora WHERE corpusid = $1").unwrap()
+    .query(&[&corpus.id.unwrap()]).unwrap().get(0).get(0);
+  assert_eq!(corpus_count, 0);
+  let task_count : i64 = backend.connection.prepare("SELECT count(*) FROM tasks WHERE taskid = $1").unwrap()
+    .query(&[&task.id.unwrap()]).unwrap().get(0).get(0);
+  assert_eq!(task_count, 0);
+}
@@ -0,0 +1,66 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+extern crate cortex;
+
+use cortex::data::{Corpus, Service, Task, TaskStatus};
+use std::path::Path;
+
+fn mock_corpus() -> Corpus {
+  Corpus {
+    id : Some(7),
+    name : "constructor corpus".to_string(),
+    path : "tests/data/".to_string(),
+    complex : true
+  }
+}
+
+#[test]
+fn new_task_is_queued() {
+  let service = Service {
+    id : Some(42),
+    name : "constructor_service".to_string(),
+    version : 0.1,
+    inputformat : "tex".to_string(),
+    outputformat : "html".to_string(),
+    inputconverter : Some("import".to_string()),
+    complex : true
+  };
+  let task = Task::new("/constructor/entry.zip", &service, &mock_corpus());
+  assert_eq!(task.id, None);
+  assert_eq!(task.entry, "/constructor/entry.zip");
+  assert_eq!(task.serviceid, 42);
+  assert_eq!(task.corpusid, 7);
+  assert_eq!(task.status, TaskStatus::TODO.raw());
+}
+
+#[test]
+fn imported_task_belongs_to_import() {
+  let task = Task::imported("/constructor/entry.zip", &mock_corpus());
+  assert_eq!(task.id, None);
+  assert_eq!(task.entry, "/constructor/entry.zip");
+  assert_eq!(task.serviceid, 2);
+  assert_eq!(task.corpusid, 7);
+  assert_eq!(task.status, TaskStatus::NoProblem.raw());
+}
+
+#[test]
+fn report_lists_artifact_formats() {
+  let task = Task::imported("tests/data/1508.01222/1508.01222.zip", &mock_corpus());
+  let report = task.generate_report(Path::new("tests/data/1508.01222/1508.01222.zip"));
+  // No cortex.log in a source archive, but its entries are still recorded
+  assert_eq!(report.status.raw(), TaskStatus::Fatal.raw());
+  assert_eq!(report.artifact_formats, vec!["pdf".to_string(), "tex".to_string()]);
+}
+
+#[test]
+fn cancelled_status_apart_from_blocked() {
+  assert_eq!(TaskStatus::from_raw(TaskStatus::Cancelled.raw()).to_key(), "cancelled");
+  for raw in vec![-6, -7, -65535].into_iter() {
+    assert_eq!(TaskStatus::from_raw(raw).to_key(), "blocked");
+  }
+  assert_eq!(TaskStatus::from_key("blocked").raw(), -6);
+}
@@ -9,9 +9,10 @@ extern crate pericortex;
 
 use cortex::backend::{Backend, TEST_DB_ADDRESS};
 use cortex::data::{Corpus,Service, Task, TaskStatus};
-use cortex::manager::{TaskManager};
+use cortex::manager::{TaskManager, VentilatorStats};
 use pericortex::worker::{EchoWorker, Worker};
 use cortex::importer::Importer;
+use std::sync::Arc;
 use std::thread;
 #[test]
 fn mock_round_trip() {
@@ -46,7 +47,8 @@ fn mock_round_trip() {
       entry : abs_entry.clone(),
       serviceid : 2, // Import service always has id 2
       corpusid : mock_corpus.id.unwrap().clone(),
-      status : TaskStatus::NoProblem.raw()
+      status : TaskStatus::NoProblem.raw(),
+      metadata : None
     }).unwrap();
   test_backend.add(
     Task {
@@ -54,7 +56,8 @@ fn mock_round_trip() {
       entry : abs_entry.clone(),
       serviceid : echo_service.id.unwrap().clone(),
       corpusid : mock_corpus.id.unwrap().clone(),
-      status : TaskStatus::TODO.raw()
+      status : TaskStatus::TODO.raw(),
+      metadata : None
     }).unwrap();
   
   // Start up a ventilator/sink pair
@@ -64,7 +67,13 @@ fn mock_round_trip() {
       result_port : 5556,
       queue_size : 100000,
       message_size : 100,
-      backend_address : TEST_DB_ADDRESS.clone().to_string()
+      backend_address : TEST_DB_ADDRESS.clone().to_string(),
+      maintenance_interval : 60,
+      task_timeout : 3600,
+      send_payload : true,
+      acknowledge_results : false,
+      discard_output : false,
+      ventilator_stats : Arc::new(VentilatorStats::default())
     };
     assert!(manager.start(job_limit).is_ok());
   });
@@ -0,0 +1,88 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+extern crate cortex;
+extern crate zmq;
+
+use std::fs::File;
+use cortex::backend::Backend;
+use cortex::data::{CortexORM, Service};
+use cortex::error::CortexError;
+
+fn postgres_failure(backend : &Backend) -> Result<(), CortexError> {
+  try!(backend.connection.execute("SELECT * FROM no_such_cortex_table", &[]));
+  Ok(())
+}
+fn zmq_failure() -> Result<(), CortexError> {
+  let mut context = zmq::Context::new();
+  let mut socket = try!(context.socket(zmq::PULL));
+  try!(socket.bind("no-such-transport://nowhere"));
+  Ok(())
+}
+fn io_failure() -> Result<(), CortexError> {
+  try!(File::open("tests/data/no_such_cortex_file"));
+  Ok(())
+}
+
+#[test]
+fn postgres_error_variant() {
+  let backend = Backend::testdb();
+  match postgres_failure(&backend) {
+    Err(CortexError::Postgres(_)) => {},
+    other => panic!("Expected a Postgres error, got {:?}", other)
+  }
+}
+
+#[test]
+fn zmq_error_variant() {
+  match zmq_failure() {
+    Err(CortexError::Zmq(_)) => {},
+    other => panic!("Expected a Zmq error, got {:?}", other)
+  }
+}
+
+#[test]
+fn io_error_variant() {
+  match io_failure() {
+    Err(CortexError::Io(_)) => {},
+    other => panic!("Expected an Io error, got {:?}", other)
+  }
+}
+
+#[test]
+fn protocol_error_variant() {
+  let error = CortexError::Protocol("unexpected empty taskid frame".to_string());
+  assert_eq!(error.to_string(), "Protocol error: unexpected empty taskid frame");
+}
+
+#[test]
+fn already_exists_error_variant() {
+  let backend = Backend::testdb();
+  let service = Service {
+    id : None,
+    name : "duplicate_insert_test_service".to_string(),
+    version : 0.1,
+    inputformat : "tex".to_string(),
+    outputformat : "html".to_string(),
+    inputconverter : Some("import".to_string()),
+    complex : true
+  };
+  // Start from a clean slate, then insert the same name and version twice
+  if let Some(existing) = service.select_by_key(&backend.connection).unwrap() {
+    backend.delete(&existing).unwrap();
+  }
+  assert!(service.insert(&backend.connection).is_ok());
+  match service.insert(&backend.connection) {
+    Err(CortexError::AlreadyExists(ref item)) => assert!(item.contains("duplicate_insert_test_service")),
+    other => panic!("Expected an AlreadyExists error, got {:?}", other)
+  }
+}
+
+#[test]
+fn invalid_error_variant() {
+  let error = CortexError::Invalid("unknown severity".to_string());
+  assert_eq!(error.to_string(), "Invalid argument: unknown severity");
+}
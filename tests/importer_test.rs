@@ -6,11 +6,13 @@
 // except according to those terms.
 extern crate cortex;
 use cortex::importer::*;
-use cortex::backend::Backend;
+use cortex::backend::{Backend, ReimportSummary};
 use cortex::data::Corpus;
 
 use std::vec::Vec;
 use std::fs;
+use std::env;
+use std::fs::File;
 // use std::io::{Error};
 
 fn assert_files(files:Vec<&str>) -> Result<(),std::io::Error> {
@@ -93,4 +95,41 @@ fn can_import_complex() {
   ]);
   assert!(dirs_removed_ok.is_ok());
   
+}
+
+#[test]
+fn can_reimport_changed_corpus() {
+  let test_backend = Backend::testdb();
+  let mut corpus_dir = env::temp_dir();
+  corpus_dir.push("cortex_reimport_test");
+  let corpus_path = corpus_dir.to_str().unwrap().to_string();
+  let _ = fs::remove_dir_all(&corpus_path);
+  for name in vec!["kept", "removed"].iter() {
+    fs::create_dir_all(corpus_path.clone() + "/" + name).unwrap();
+    File::create(corpus_path.clone() + "/" + name + "/" + name + ".tex").unwrap();
+  }
+  let corpus = test_backend.add(
+    Corpus {
+      id: None,
+      path : corpus_path.clone(),
+      name : "reimport test".to_string(),
+      complex : false }).unwrap();
+  let importer = Importer {
+    corpus: corpus.clone(),
+    backend: Backend::testdb(),
+    cwd : Importer::cwd() };
+  assert_eq!( importer.process(), Ok(()) );
+
+  // One entry disappears, another one shows up
+  fs::remove_dir_all(corpus_path.clone() + "/removed").unwrap();
+  fs::create_dir_all(corpus_path.clone() + "/added").unwrap();
+  File::create(corpus_path.clone() + "/added/added.tex").unwrap();
+
+  let summary = test_backend.reimport(&corpus, true).unwrap();
+  assert_eq!(summary, ReimportSummary { added : 1, removed : 1, unchanged : 1 });
+  // A second pass is a no-op
+  let repeat_summary = test_backend.reimport(&corpus, true).unwrap();
+  assert_eq!(repeat_summary, ReimportSummary { added : 0, removed : 0, unchanged : 2 });
+
+  assert!(fs::remove_dir_all(&corpus_path).is_ok());
 }
\ No newline at end of file
@@ -0,0 +1,17 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+extern crate cortex;
+
+use cortex::backend::Backend;
+
+// Note: kept apart from the backend tests, which run in parallel and would lose their tables to the hard reset
+#[test]
+fn init_tables() {
+  let backend = Backend::testdb();
+  assert!(backend.setup_task_tables().is_ok());
+  assert!(!backend.needs_init().unwrap());
+}
@@ -0,0 +1,68 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+extern crate cortex;
+extern crate log;
+extern crate zmq;
+
+use cortex::backend::{Backend, TEST_DB_ADDRESS};
+use cortex::manager::{Server, VentilatorStats};
+use log::{LogLevel, LogLevelFilter, LogMetadata, LogRecord};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+struct CapturingLogger {
+  records : Arc<Mutex<Vec<(LogLevel, String)>>>
+}
+impl log::Log for CapturingLogger {
+  fn enabled(&self, metadata : &LogMetadata) -> bool {
+    metadata.level() <= LogLevel::Info
+  }
+  fn log(&self, record : &LogRecord) {
+    if self.enabled(record.metadata()) {
+      self.records.lock().unwrap().push((record.level(), format!("{}", record.args())));
+    }
+  }
+}
+
+#[test]
+fn ventilator_logs_binding() {
+  let records = Arc::new(Mutex::new(Vec::new()));
+  let logger_records = records.clone();
+  log::set_logger(|max_log_level| {
+    max_log_level.set(LogLevelFilter::Info);
+    Box::new(CapturingLogger { records : logger_records })
+  }).unwrap();
+
+  let ventilator_thread = thread::spawn(move || {
+    let server = Server {
+      port : 5661,
+      queue_size : 100,
+      message_size : 100,
+      backend : Backend::testdb(),
+      backend_address : TEST_DB_ADDRESS.to_string(),
+      send_payload : true,
+      acknowledge_results : false,
+      ventilator_stats : Arc::new(VentilatorStats::default()),
+      discard_output : false
+    };
+    assert!(server.start_ventilator(Arc::new(Mutex::new(HashMap::new())), Arc::new(Mutex::new(HashMap::new())),
+      Arc::new(Mutex::new(Vec::new())), Some(1)).is_ok());
+  });
+  // A single request for an unknown service completes the ventilator's job limit
+  let mut context = zmq::Context::new();
+  let mut requester = context.socket(zmq::DEALER).unwrap();
+  assert!(requester.connect("tcp://localhost:5661").is_ok());
+  requester.send_str("no_such_logging_service", 0).unwrap();
+  assert!(ventilator_thread.join().is_ok());
+  requester.close().unwrap();
+  context.destroy().unwrap();
+
+  let records = records.lock().unwrap();
+  assert!(records.iter().any(|&(level, ref message)| level == LogLevel::Info && message.contains("5661")),
+    "No info record of the binding in {:?}", *records);
+}
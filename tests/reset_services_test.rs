@@ -0,0 +1,70 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+extern crate cortex;
+
+use cortex::backend::Backend;
+use cortex::data::{Corpus, Service, Task, TaskStatus};
+
+// Note: kept apart from the backend tests, which run in parallel and would lose their services to the reset
+#[test]
+fn reset_services_keeps_system_services_and_corpora() {
+  let test_backend = Backend::testdb();
+  if test_backend.needs_init().unwrap() {
+    test_backend.setup_task_tables().unwrap();
+  }
+  let corpus = test_backend.add(
+    Corpus {
+      id : None,
+      name : "reset services test corpus".to_string(),
+      path : "tests/data/".to_string(),
+      complex : true,
+    }).unwrap();
+  let import_service = Service::from_name(&test_backend.connection, "import".to_string()).unwrap().unwrap();
+  let imported_task = test_backend.add(
+    Task {
+      id : None,
+      entry : "/reset_services/entry.zip".to_string(),
+      serviceid : import_service.id.unwrap(),
+      corpusid : corpus.id.unwrap(),
+      status : TaskStatus::NoProblem.raw(),
+      metadata : None
+    }).unwrap();
+  let mut services = Vec::new();
+  for name in vec!["reset_services_first_test_service", "reset_services_second_test_service"].iter() {
+    let service = test_backend.add(
+      Service {
+        id : None,
+        name : name.to_string(),
+        version : 0.1,
+        inputformat : "tex".to_string(),
+        outputformat : "html".to_string(),
+        inputconverter : Some("import".to_string()),
+        complex : true
+      }).unwrap();
+    test_backend.add(
+      Task {
+        id : None,
+        entry : "/reset_services/entry.zip".to_string(),
+        serviceid : service.id.unwrap(),
+        corpusid : corpus.id.unwrap(),
+        status : TaskStatus::TODO.raw(),
+        metadata : None
+      }).unwrap();
+    services.push(service);
+  }
+
+  assert!(test_backend.reset_services().is_ok());
+  let mut remaining = test_backend.services().into_iter().map(|service| service.name).collect::<Vec<_>>();
+  remaining.sort();
+  assert_eq!(remaining, vec!["import".to_string(), "init".to_string()]);
+  let orphan_tasks : i64 = test_backend.connection.prepare("SELECT count(*) FROM tasks WHERE serviceid = ANY($1)").unwrap()
+    .query(&[&services.iter().map(|service| service.id.unwrap()).collect::<Vec<i32>>()]).unwrap().get(0).get(0);
+  assert_eq!(orphan_tasks, 0);
+  // Corpora, and the tasks of the system services, are kept
+  assert!(test_backend.corpora().iter().any(|kept| kept.id == corpus.id));
+  assert_eq!(test_backend.sync(&imported_task).unwrap().status, TaskStatus::NoProblem.raw());
+}
@@ -0,0 +1,328 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+extern crate cortex;
+extern crate time;
+extern crate zmq;
+
+use cortex::backend::{Backend, TEST_DB_ADDRESS};
+use cortex::data::{Corpus, Service, Task, TaskMessage, TaskProgress, TaskReport, TaskStatus};
+use cortex::manager::{Server, VentilatorStats};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn sink_acknowledges_persisted_results() {
+  let test_backend = Backend::testdb();
+  if test_backend.needs_init().unwrap() {
+    test_backend.setup_task_tables().unwrap();
+  }
+  let corpus = test_backend.add(
+    Corpus {
+      id : None,
+      name : "acknowledging sink corpus".to_string(),
+      path : "tests/data/".to_string(),
+      complex : true,
+    }).unwrap();
+  let init_service = Service::from_name(&test_backend.connection, "init".to_string()).unwrap().unwrap();
+  let task = test_backend.add(
+    Task {
+      id : None,
+      entry : "/acknowledged/entry.zip".to_string(),
+      serviceid : init_service.id.unwrap(),
+      corpusid : corpus.id.unwrap(),
+      status : TaskStatus::TODO.raw(),
+      metadata : None
+    }).unwrap();
+
+  // The task is in progress, as if dispatched by the ventilator
+  let mut services = HashMap::new();
+  services.insert("init".to_string(), Some(init_service.clone()));
+  let mut progress_queue = HashMap::new();
+  progress_queue.insert(task.id.unwrap(), TaskProgress {
+    task : task.clone(),
+    created_at : time::get_time().sec,
+    retries : 0,
+    worker_id : None,
+    // Sent to the worker a quarter second ago
+    dispatched_at_ms : Some(TaskProgress::now_ms() - 250)
+  });
+  let services_arc = Arc::new(Mutex::new(services));
+  let progress_queue_arc = Arc::new(Mutex::new(progress_queue));
+  // A report pending in the shared queue, owned by the finalize thread, which the acknowledging sink must leave alone
+  let pending_report = TaskReport {
+    task : Task { id : Some(-1), entry : "/acknowledged/pending.zip".to_string(), serviceid : init_service.id.unwrap(),
+      corpusid : corpus.id.unwrap(), status : TaskStatus::TODO.raw(), metadata : None },
+    status : TaskStatus::NoProblem,
+    messages : Vec::new(),
+    duration_ms : None,
+    worker_id : None,
+    artifact_formats : Vec::new()
+  };
+  let done_queue_arc = Arc::new(Mutex::new(vec![pending_report]));
+  let sink_done_queue_arc = done_queue_arc.clone();
+  let sink_thread = thread::spawn(move || {
+    let server = Server {
+      port : 5660,
+      queue_size : 100,
+      message_size : 100,
+      backend : Backend::testdb(),
+      backend_address : TEST_DB_ADDRESS.to_string(),
+      send_payload : false,
+      acknowledge_results : true,
+      ventilator_stats : Arc::new(VentilatorStats::default()),
+      discard_output : false
+    };
+    assert!(server.start_sink(services_arc, progress_queue_arc, sink_done_queue_arc, Some(1)).is_ok());
+  });
+
+  let mut context = zmq::Context::new();
+  let mut requester = context.socket(zmq::REQ).unwrap();
+  assert!(requester.connect("tcp://localhost:5660").is_ok());
+  requester.send_str("init", zmq::SNDMORE).unwrap();
+  requester.send_str(&task.id.unwrap().to_string(), zmq::SNDMORE).unwrap();
+  requester.send(&[], 0).unwrap();
+  let mut ack = zmq::Message::new().unwrap();
+  requester.recv(&mut ack, 0).unwrap();
+  assert_eq!(ack.as_str(), Some("ok"));
+  // The acknowledgement is only sent once the result is in the Task store
+  assert_eq!(test_backend.sync(&task).unwrap().status, TaskStatus::NoProblem.raw());
+  // The recorded duration spans from dispatch to result, in milliseconds
+  let duration_ms : Option<i32> = test_backend.connection.prepare("SELECT duration_ms FROM tasks WHERE taskid = $1").unwrap()
+    .query(&[&task.id.unwrap()]).unwrap().get(0).get(0);
+  assert!(duration_ms.unwrap() >= 250 && duration_ms.unwrap() < 60000);
+
+  assert!(sink_thread.join().is_ok());
+  // The acknowledged report was written on its own, the shared queue is untouched
+  let done_queue = done_queue_arc.lock().unwrap();
+  assert_eq!(done_queue.len(), 1);
+  assert_eq!(done_queue[0].task.entry, "/acknowledged/pending.zip");
+  requester.close().unwrap();
+  context.destroy().unwrap();
+}
+
+#[test]
+fn sink_discards_output_in_lint_mode() {
+  let test_backend = Backend::testdb();
+  if test_backend.needs_init().unwrap() {
+    test_backend.setup_task_tables().unwrap();
+  }
+  let corpus = test_backend.add(
+    Corpus {
+      id : None,
+      name : "discarding sink corpus".to_string(),
+      path : "tests/data/".to_string(),
+      complex : true,
+    }).unwrap();
+  let service = test_backend.add(
+    Service {
+      id : None,
+      name : "discard_test_service".to_string(),
+      version : 0.1,
+      inputformat : "tex".to_string(),
+      outputformat : "html".to_string(),
+      inputconverter : Some("import".to_string()),
+      complex : true
+    }).unwrap();
+  let mut entry_dir = env::temp_dir();
+  entry_dir.push("cortex_discard_test");
+  fs::create_dir_all(&entry_dir).unwrap();
+  let entry = entry_dir.join("entry.zip").to_str().unwrap().to_string();
+  let result_path = entry_dir.join("discard_test_service.zip");
+  let task = test_backend.add(
+    Task {
+      id : None,
+      entry : entry,
+      serviceid : service.id.unwrap(),
+      corpusid : corpus.id.unwrap(),
+      status : TaskStatus::TODO.raw(),
+      metadata : None
+    }).unwrap();
+
+  let mut services = HashMap::new();
+  services.insert("discard_test_service".to_string(), Some(service.clone()));
+  let mut progress_queue = HashMap::new();
+  progress_queue.insert(task.id.unwrap(), TaskProgress {
+    task : task.clone(),
+    created_at : time::get_time().sec,
+    retries : 0,
+    worker_id : None,
+    dispatched_at_ms : None
+  });
+  let services_arc = Arc::new(Mutex::new(services));
+  let progress_queue_arc = Arc::new(Mutex::new(progress_queue));
+  let sink_thread = thread::spawn(move || {
+    let server = Server {
+      port : 5661,
+      queue_size : 100,
+      message_size : 100,
+      backend : Backend::testdb(),
+      backend_address : TEST_DB_ADDRESS.to_string(),
+      send_payload : false,
+      acknowledge_results : true,
+      ventilator_stats : Arc::new(VentilatorStats::default()),
+      discard_output : true
+    };
+    assert!(server.start_sink(services_arc, progress_queue_arc, Arc::new(Mutex::new(Vec::new())), Some(1)).is_ok());
+  });
+
+  // A result archive without a cortex.log, which the sink reports as fatal
+  let mut result_bytes = Vec::new();
+  File::open("tests/data/1206.5501/1206.5501.zip").unwrap().read_to_end(&mut result_bytes).unwrap();
+  let mut context = zmq::Context::new();
+  let mut requester = context.socket(zmq::REQ).unwrap();
+  assert!(requester.connect("tcp://localhost:5661").is_ok());
+  requester.send_str("discard_test_service", zmq::SNDMORE).unwrap();
+  requester.send_str(&task.id.unwrap().to_string(), zmq::SNDMORE).unwrap();
+  requester.send(&result_bytes, 0).unwrap();
+  let mut ack = zmq::Message::new().unwrap();
+  requester.recv(&mut ack, 0).unwrap();
+  assert_eq!(ack.as_str(), Some("ok"));
+
+  assert_eq!(test_backend.sync(&task).unwrap().status, TaskStatus::Fatal.raw());
+  assert!(!result_path.exists());
+
+  assert!(sink_thread.join().is_ok());
+  requester.close().unwrap();
+  context.destroy().unwrap();
+}
+
+#[test]
+fn sink_retries_failed_persist() {
+  let test_backend = Backend::testdb();
+  if test_backend.needs_init().unwrap() {
+    test_backend.setup_task_tables().unwrap();
+  }
+  let corpus = test_backend.add(
+    Corpus {
+      id : None,
+      name : "retrying sink corpus".to_string(),
+      path : "tests/data/".to_string(),
+      complex : true,
+    }).unwrap();
+  let init_service = Service::from_name(&test_backend.connection, "init".to_string()).unwrap().unwrap();
+  let task = test_backend.add(
+    Task {
+      id : None,
+      entry : "/retried/entry.zip".to_string(),
+      serviceid : init_service.id.unwrap(),
+      corpusid : corpus.id.unwrap(),
+      status : TaskStatus::TODO.raw(),
+      metadata : None
+    }).unwrap();
+
+  // Hold a lock on the task for a while, so that the first writes of the sink time out
+  let (locked_sender, locked_receiver) = channel();
+  let locked_taskid = task.id.unwrap();
+  let locker_thread = thread::spawn(move || {
+    let locker = Backend::testdb();
+    let trans = locker.connection.transaction().unwrap();
+    trans.execute("SELECT taskid FROM tasks WHERE taskid=$1 FOR UPDATE", &[&locked_taskid]).unwrap();
+    locked_sender.send(()).unwrap();
+    thread::sleep(Duration::from_millis(500));
+    trans.set_commit();
+    trans.finish().unwrap();
+  });
+  locked_receiver.recv().unwrap();
+
+  let mut services = HashMap::new();
+  services.insert("init".to_string(), Some(init_service.clone()));
+  let mut progress_queue = HashMap::new();
+  progress_queue.insert(task.id.unwrap(), TaskProgress {
+    task : task.clone(),
+    created_at : time::get_time().sec,
+    retries : 0,
+    worker_id : None,
+    dispatched_at_ms : None
+  });
+  let services_arc = Arc::new(Mutex::new(services));
+  let progress_queue_arc = Arc::new(Mutex::new(progress_queue));
+  let sink_thread = thread::spawn(move || {
+    let sink_backend = Backend::testdb();
+    sink_backend.connection.execute("SET lock_timeout = 100", &[]).unwrap();
+    let server = Server {
+      port : 5663,
+      queue_size : 100,
+      message_size : 100,
+      backend : sink_backend,
+      backend_address : TEST_DB_ADDRESS.to_string(),
+      send_payload : false,
+      acknowledge_results : true,
+      ventilator_stats : Arc::new(VentilatorStats::default()),
+      discard_output : false
+    };
+    assert!(server.start_sink(services_arc, progress_queue_arc, Arc::new(Mutex::new(Vec::new())), Some(1)).is_ok());
+  });
+
+  let mut context = zmq::Context::new();
+  let mut requester = context.socket(zmq::REQ).unwrap();
+  assert!(requester.connect("tcp://localhost:5663").is_ok());
+  requester.send_str("init", zmq::SNDMORE).unwrap();
+  requester.send_str(&task.id.unwrap().to_string(), zmq::SNDMORE).unwrap();
+  requester.send(&[], 0).unwrap();
+  let mut ack = zmq::Message::new().unwrap();
+  requester.recv(&mut ack, 0).unwrap();
+  // Only acknowledged once a retry got through
+  assert_eq!(ack.as_str(), Some("ok"));
+  assert_eq!(test_backend.sync(&task).unwrap().status, TaskStatus::NoProblem.raw());
+
+  assert!(locker_thread.join().is_ok());
+  assert!(sink_thread.join().is_ok());
+  requester.close().unwrap();
+  context.destroy().unwrap();
+}
+
+#[test]
+fn finalize_requeues_failed_batches() {
+  let mut test_backend = Backend::testdb();
+  if test_backend.needs_init().unwrap() {
+    test_backend.setup_task_tables().unwrap();
+  }
+  let corpus = test_backend.add(
+    Corpus {
+      id : None,
+      name : "requeueing finalize corpus".to_string(),
+      path : "tests/data/".to_string(),
+      complex : true,
+    }).unwrap();
+  let init_service = Service::from_name(&test_backend.connection, "init".to_string()).unwrap().unwrap();
+  let task = test_backend.add(
+    Task {
+      id : None,
+      entry : "/requeued/entry.zip".to_string(),
+      serviceid : init_service.id.unwrap(),
+      corpusid : corpus.id.unwrap(),
+      status : TaskStatus::TODO.raw(),
+      metadata : None
+    }).unwrap();
+  // A message category too long for the logs table, so that every write of the batch fails
+  let failing_report = TaskReport {
+    task : task.clone(),
+    status : TaskStatus::Error,
+    messages : vec![TaskMessage {
+      severity : "error".to_string(),
+      category : "c".repeat(60),
+      what : "too_long".to_string(),
+      details : String::new()
+    }],
+    duration_ms : None,
+    worker_id : None,
+    artifact_formats : Vec::new()
+  };
+  let done_queue_arc = Arc::new(Mutex::new(vec![failing_report]));
+
+  // The finalize pass gives up without panicking, and keeps the batch queued for a later pass
+  assert!(!Server::mark_done_arc(&mut test_backend, &done_queue_arc));
+  assert_eq!(done_queue_arc.lock().unwrap().len(), 1);
+  assert_eq!(test_backend.sync(&task).unwrap().status, TaskStatus::TODO.raw());
+}
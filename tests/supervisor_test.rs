@@ -0,0 +1,93 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+extern crate cortex;
+extern crate time;
+
+use cortex::backend::Backend;
+use cortex::data::{Corpus, Service, Task, TaskProgress, TaskStatus};
+use cortex::manager::Supervisor;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn supervisor_requeues_expired_tasks() {
+  let test_backend = Backend::testdb();
+  if test_backend.needs_init().unwrap() {
+    test_backend.setup_task_tables().unwrap();
+  }
+  let corpus = test_backend.add(
+    Corpus {
+      id : None,
+      name : "supervisor test corpus".to_string(),
+      path : "tests/data/".to_string(),
+      complex : true,
+    }).unwrap();
+  let service = test_backend.add(
+    Service {
+      id : None,
+      name : "supervisor_test_service".to_string(),
+      version : 0.1,
+      inputformat : "tex".to_string(),
+      outputformat : "html".to_string(),
+      inputconverter : Some("import".to_string()),
+      complex : true
+    }).unwrap();
+  let mut tasks = Vec::new();
+  for entry in vec!["/supervisor/expired.zip", "/supervisor/fresh.zip"].iter() {
+    tasks.push(test_backend.add(
+      Task {
+        id : None,
+        entry : entry.to_string(),
+        serviceid : service.id.unwrap(),
+        corpusid : corpus.id.unwrap(),
+        status : TaskStatus::TODO.raw(),
+        metadata : None
+      }).unwrap());
+  }
+  // Both tasks are claimed, but only one of them a long time ago
+  let expired_task = test_backend.claim_task(tasks[0].id.unwrap()).unwrap().unwrap();
+  let fresh_task = test_backend.claim_task(tasks[1].id.unwrap()).unwrap().unwrap();
+  test_backend.connection.execute("UPDATE tasks SET claimed_at = claimed_at - 7200 WHERE taskid = $1",
+    &[&expired_task.id.unwrap()]).unwrap();
+
+  // The dispatcher tracks both tasks as in progress
+  let mut progress_queue = HashMap::new();
+  for task in vec![&expired_task, &fresh_task].into_iter() {
+    progress_queue.insert(task.id.unwrap(), TaskProgress {
+      task : task.clone(),
+      created_at : time::get_time().sec,
+      retries : 0,
+      worker_id : None,
+      dispatched_at_ms : None
+    });
+  }
+  let progress_queue_arc = Arc::new(Mutex::new(progress_queue));
+  let supervisor_progress_queue_arc = progress_queue_arc.clone();
+
+  let shutdown = Arc::new(AtomicBool::new(false));
+  let supervisor_shutdown = shutdown.clone();
+  let supervisor_thread = thread::spawn(move || {
+    let supervisor = Supervisor {
+      interval : 1,
+      task_timeout : 3600,
+      backend : Backend::testdb()
+    };
+    assert!(supervisor.start(supervisor_progress_queue_arc, Arc::new(Mutex::new(Vec::new())), supervisor_shutdown).is_ok());
+  });
+  thread::sleep(Duration::new(2,0));
+  shutdown.store(true, Ordering::SeqCst);
+  assert!(supervisor_thread.join().is_ok());
+
+  assert_eq!(test_backend.sync(&expired_task).unwrap().status, TaskStatus::TODO.raw());
+  assert_eq!(test_backend.sync(&fresh_task).unwrap().status, fresh_task.status);
+  let progress_queue = progress_queue_arc.lock().unwrap();
+  assert!(!progress_queue.contains_key(&expired_task.id.unwrap()));
+  assert!(progress_queue.contains_key(&fresh_task.id.unwrap()));
+}
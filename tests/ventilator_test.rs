@@ -0,0 +1,328 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+extern crate cortex;
+extern crate rustc_serialize;
+extern crate zmq;
+
+use cortex::backend::{Backend, TEST_DB_ADDRESS};
+use cortex::data::{Corpus, Service, Task, TaskStatus};
+use cortex::manager::{Server, VentilatorStats};
+use rustc_serialize::json::Json;
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::Ordering;
+use std::thread;
+
+#[test]
+fn ventilator_dispatches_batches() {
+  let test_backend = Backend::testdb();
+  if test_backend.needs_init().unwrap() {
+    test_backend.setup_task_tables().unwrap();
+  }
+  let corpus = test_backend.add(
+    Corpus {
+      id : None,
+      name : "batch ventilator corpus".to_string(),
+      path : "tests/data/".to_string(),
+      complex : true,
+    }).unwrap();
+  let service = test_backend.add(
+    Service {
+      id : None,
+      name : "batch_test_service".to_string(),
+      version : 0.1,
+      inputformat : "tex".to_string(),
+      outputformat : "tex".to_string(),
+      inputconverter : Some("import".to_string()),
+      complex : true
+    }).unwrap();
+  for index in 0..3 {
+    test_backend.add(
+      Task {
+        id : None,
+        entry : format!("/tmp/batch_ventilator_{}.tex", index),
+        serviceid : service.id.unwrap(),
+        corpusid : corpus.id.unwrap(),
+        status : TaskStatus::TODO.raw(),
+        metadata : None
+      }).unwrap();
+  }
+
+  let progress_queue_arc = Arc::new(Mutex::new(HashMap::new()));
+  let progress_queue_check = progress_queue_arc.clone();
+  let stats_arc = Arc::new(VentilatorStats::default());
+  let server_stats_arc = stats_arc.clone();
+  let ventilator_thread = thread::spawn(move || {
+    let server = Server {
+      port : 5657,
+      queue_size : 100,
+      message_size : 100,
+      backend : Backend::testdb(),
+      backend_address : TEST_DB_ADDRESS.to_string(),
+      send_payload : true,
+      acknowledge_results : false,
+      ventilator_stats : server_stats_arc,
+      discard_output : false
+    };
+    assert!(server.start_ventilator(Arc::new(Mutex::new(HashMap::new())), progress_queue_arc,
+      Arc::new(Mutex::new(Vec::new())), Some(1)).is_ok());
+  });
+
+  // Request up to 10 tasks in a single round-trip
+  let mut context = zmq::Context::new();
+  let mut requester = context.socket(zmq::DEALER).unwrap();
+  assert!(requester.connect("tcp://localhost:5657").is_ok());
+  requester.send_str("batch_test_service", zmq::SNDMORE).unwrap();
+  requester.send_str("10", 0).unwrap();
+  let mut reply = zmq::Message::new().unwrap();
+  requester.recv(&mut reply, 0).unwrap();
+  let batch = Json::from_str(reply.as_str().unwrap()).unwrap();
+  let batch_tasks = batch.as_array().unwrap();
+  assert_eq!(batch_tasks.len(), 3);
+  for task_json in batch_tasks.iter() {
+    assert_eq!(task_json.find("serviceid").unwrap().as_i64(), Some(service.id.unwrap() as i64));
+  }
+
+  assert!(ventilator_thread.join().is_ok());
+  assert_eq!(progress_queue_check.lock().unwrap().len(), 3);
+  assert_eq!(stats_arc.dispatched.load(Ordering::SeqCst), 3);
+  assert_eq!(stats_arc.empty_responses.load(Ordering::SeqCst), 0);
+  assert_eq!(stats_arc.errors.load(Ordering::SeqCst), 0);
+  requester.close().unwrap();
+  context.destroy().unwrap();
+}
+
+fn request_single_task(port : usize, service_name : &str) -> (String, Vec<u8>) {
+  let mut context = zmq::Context::new();
+  let mut requester = context.socket(zmq::DEALER).unwrap();
+  assert!(requester.connect(&format!("tcp://localhost:{}", port)).is_ok());
+  requester.send_str(service_name, 0).unwrap();
+  let mut taskid_msg = zmq::Message::new().unwrap();
+  requester.recv(&mut taskid_msg, 0).unwrap();
+  let taskid = taskid_msg.as_str().unwrap().to_string();
+  let mut payload = Vec::new();
+  while requester.get_rcvmore().unwrap() {
+    let mut payload_msg = zmq::Message::new().unwrap();
+    requester.recv(&mut payload_msg, 0).unwrap();
+    payload.extend(payload_msg.iter().cloned());
+  }
+  requester.close().unwrap();
+  context.destroy().unwrap();
+  (taskid, payload)
+}
+
+#[test]
+fn ventilator_payload_modes() {
+  let test_backend = Backend::testdb();
+  if test_backend.needs_init().unwrap() {
+    test_backend.setup_task_tables().unwrap();
+  }
+  let corpus = test_backend.add(
+    Corpus {
+      id : None,
+      name : "payload ventilator corpus".to_string(),
+      path : "tests/data/".to_string(),
+      complex : true,
+    }).unwrap();
+  let service = test_backend.add(
+    Service {
+      id : None,
+      name : "payload_test_service".to_string(),
+      version : 0.1,
+      inputformat : "tex".to_string(),
+      outputformat : "tex".to_string(),
+      inputconverter : Some("import".to_string()),
+      complex : true
+    }).unwrap();
+  // A payload spanning several message chunks, including non-UTF8 bytes
+  let payload_bytes = (0..250).map(|byte| byte as u8).collect::<Vec<u8>>();
+  let mut entry_path = env::temp_dir();
+  entry_path.push("cortex_payload_test.zip");
+  let entry = entry_path.to_str().unwrap().to_string();
+  File::create(&entry_path).unwrap().write_all(&payload_bytes).unwrap();
+  let task = test_backend.add(
+    Task {
+      id : None,
+      entry : entry.clone(),
+      serviceid : service.id.unwrap(),
+      corpusid : corpus.id.unwrap(),
+      status : TaskStatus::TODO.raw(),
+      metadata : None
+    }).unwrap();
+
+  for &(port, send_payload) in vec![(5658, true), (5659, false)].iter() {
+    let ventilator_thread = thread::spawn(move || {
+      let server = Server {
+        port : port,
+        queue_size : 100,
+        message_size : 100,
+        backend : Backend::testdb(),
+        backend_address : TEST_DB_ADDRESS.to_string(),
+        send_payload : send_payload,
+        acknowledge_results : false,
+        ventilator_stats : Arc::new(VentilatorStats::default()),
+        discard_output : false
+      };
+      assert!(server.start_ventilator(Arc::new(Mutex::new(HashMap::new())), Arc::new(Mutex::new(HashMap::new())),
+        Arc::new(Mutex::new(Vec::new())), Some(1)).is_ok());
+    });
+    let (taskid, payload) = request_single_task(port, "payload_test_service");
+    assert!(ventilator_thread.join().is_ok());
+    assert_eq!(taskid, task.id.unwrap().to_string());
+    if send_payload {
+      assert_eq!(payload, payload_bytes);
+    } else {
+      assert_eq!(payload, entry.as_bytes().to_vec());
+    }
+  }
+}
+
+#[test]
+fn ventilator_serves_requested_service() {
+  let test_backend = Backend::testdb();
+  if test_backend.needs_init().unwrap() {
+    test_backend.setup_task_tables().unwrap();
+  }
+  let corpus = test_backend.add(
+    Corpus {
+      id : None,
+      name : "routing ventilator corpus".to_string(),
+      path : "tests/data/".to_string(),
+      complex : true,
+    }).unwrap();
+  let mut service_tasks = HashMap::new();
+  for service_name in vec!["first_routing_test_service", "second_routing_test_service"].into_iter() {
+    let service = test_backend.add(
+      Service {
+        id : None,
+        name : service_name.to_string(),
+        version : 0.1,
+        inputformat : "tex".to_string(),
+        outputformat : "tex".to_string(),
+        inputconverter : Some("import".to_string()),
+        complex : true
+      }).unwrap();
+    let task = test_backend.add(
+      Task {
+        id : None,
+        entry : format!("/tmp/{}.tex", service_name),
+        serviceid : service.id.unwrap(),
+        corpusid : corpus.id.unwrap(),
+        status : TaskStatus::TODO.raw(),
+        metadata : None
+      }).unwrap();
+    service_tasks.insert(service_name, task);
+  }
+
+  let ventilator_thread = thread::spawn(move || {
+    let server = Server {
+      port : 5662,
+      queue_size : 100,
+      message_size : 100,
+      backend : Backend::testdb(),
+      backend_address : TEST_DB_ADDRESS.to_string(),
+      send_payload : false,
+      acknowledge_results : false,
+      ventilator_stats : Arc::new(VentilatorStats::default()),
+      discard_output : false
+    };
+    assert!(server.start_ventilator(Arc::new(Mutex::new(HashMap::new())), Arc::new(Mutex::new(HashMap::new())),
+      Arc::new(Mutex::new(Vec::new())), Some(3)).is_ok());
+  });
+
+  // Each worker only receives the task of the service it asked for
+  for service_name in vec!["second_routing_test_service", "first_routing_test_service"].into_iter() {
+    let (taskid, payload) = request_single_task(5662, service_name);
+    let task = service_tasks.get(service_name).unwrap();
+    assert_eq!(taskid, task.id.unwrap().to_string());
+    assert_eq!(payload, task.entry.as_bytes().to_vec());
+  }
+  // An unknown service is answered with an error frame
+  let (error_frame, description) = request_single_task(5662, "unknown_routing_test_service");
+  assert_eq!(error_frame, "error");
+  assert_eq!(String::from_utf8(description).unwrap(), "unknown service unknown_routing_test_service");
+  assert!(ventilator_thread.join().is_ok());
+}
+
+#[test]
+fn ventilator_skips_requeued_held_tasks() {
+  let test_backend = Backend::testdb();
+  if test_backend.needs_init().unwrap() {
+    test_backend.setup_task_tables().unwrap();
+  }
+  let corpus = test_backend.add(
+    Corpus {
+      id : None,
+      name : "requeued ventilator corpus".to_string(),
+      path : "tests/data/".to_string(),
+      complex : true,
+    }).unwrap();
+  let service = test_backend.add(
+    Service {
+      id : None,
+      name : "requeued_test_service".to_string(),
+      version : 0.1,
+      inputformat : "tex".to_string(),
+      outputformat : "tex".to_string(),
+      inputconverter : Some("import".to_string()),
+      complex : true
+    }).unwrap();
+  for index in 0..2 {
+    test_backend.add(
+      Task {
+        id : None,
+        entry : format!("/tmp/requeued_ventilator_{}.tex", index),
+        serviceid : service.id.unwrap(),
+        corpusid : corpus.id.unwrap(),
+        status : TaskStatus::TODO.raw(),
+        metadata : None
+      }).unwrap();
+  }
+
+  let ventilator_thread = thread::spawn(move || {
+    let server = Server {
+      port : 5664,
+      queue_size : 100,
+      message_size : 100,
+      backend : Backend::testdb(),
+      backend_address : TEST_DB_ADDRESS.to_string(),
+      send_payload : false,
+      acknowledge_results : false,
+      ventilator_stats : Arc::new(VentilatorStats::default()),
+      discard_output : false
+    };
+    assert!(server.start_ventilator(Arc::new(Mutex::new(HashMap::new())), Arc::new(Mutex::new(HashMap::new())),
+      Arc::new(Mutex::new(Vec::new())), Some(2)).is_ok());
+  });
+
+  // The first request fetches both tasks, dispatching one and holding the other
+  let (first_taskid, _) = request_single_task(5664, "requeued_test_service");
+  // Requeue both in the Task store, as the supervisor does for expired claims
+  test_backend.connection.execute("UPDATE tasks SET status=$1, claimed_at=NULL WHERE serviceid=$2",
+    &[&TaskStatus::TODO.raw(), &service.id.unwrap()]).unwrap();
+
+  // The refetch of a batch request finds both tasks again, but the dispatched one is still in progress
+  // and the other one is still queued, so each is only served once
+  let mut context = zmq::Context::new();
+  let mut requester = context.socket(zmq::DEALER).unwrap();
+  assert!(requester.connect("tcp://localhost:5664").is_ok());
+  requester.send_str("requeued_test_service", zmq::SNDMORE).unwrap();
+  requester.send_str("10", 0).unwrap();
+  let mut reply = zmq::Message::new().unwrap();
+  requester.recv(&mut reply, 0).unwrap();
+  let batch = Json::from_str(reply.as_str().unwrap()).unwrap();
+  let batch_tasks = batch.as_array().unwrap();
+  assert_eq!(batch_tasks.len(), 1);
+  assert!(batch_tasks[0].find("id").unwrap().as_i64().unwrap().to_string() != first_taskid);
+
+  assert!(ventilator_thread.join().is_ok());
+  requester.close().unwrap();
+  context.destroy().unwrap();
+}
@@ -0,0 +1,290 @@
+// Copyright 2015 Deyan Ginev. See the LICENSE
+// file at the top-level directory of this distribution.
+//
+// Licensed under the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+extern crate cortex;
+extern crate pericortex;
+
+use cortex::backend::{Backend, TEST_DB_ADDRESS};
+use cortex::data::{Corpus, Service, Task, TaskStatus};
+use cortex::error::CortexError;
+use cortex::importer::Importer;
+use cortex::manager::{TaskManager, VentilatorStats};
+use cortex::worker::{CommandRetry, CommandWorker, ConverterChain};
+use pericortex::worker::Worker;
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+fn flaky_command(script : &str) -> Command {
+  let mut command = Command::new("sh");
+  command.arg("-c").arg(script);
+  command
+}
+
+#[test]
+fn command_retried_on_transient_failure() {
+  let mut marker = env::temp_dir();
+  marker.push("cortex_command_retry_marker");
+  let _ = fs::remove_file(&marker);
+  // Fails with EX_TEMPFAIL on the first attempt, succeeds on the next
+  let script = format!("if [ -e {0} ]; then exit 0; else touch {0}; exit 75; fi", marker.to_str().unwrap());
+  let retry = CommandRetry {
+    max_internal_retries : 2,
+    transient_exit_codes : vec![75],
+    delay : Duration::from_millis(10)
+  };
+  let output = retry.output(|| flaky_command(&script)).unwrap();
+  assert!(output.status.success());
+
+  // Without internal retries, the transient failure is reported
+  fs::remove_file(&marker).unwrap();
+  let no_retry = CommandRetry { max_internal_retries : 0, .. retry };
+  let output = no_retry.output(|| flaky_command(&script)).unwrap();
+  assert_eq!(output.status.code(), Some(75));
+  fs::remove_file(&marker).unwrap();
+}
+
+#[test]
+fn command_not_retried_on_deterministic_failure() {
+  let mut attempts_log = env::temp_dir();
+  attempts_log.push("cortex_command_retry_attempts");
+  let _ = fs::remove_file(&attempts_log);
+  let script = format!("echo attempt >> {}; exit 1", attempts_log.to_str().unwrap());
+  let retry = CommandRetry {
+    max_internal_retries : 3,
+    transient_exit_codes : vec![75],
+    delay : Duration::from_millis(10)
+  };
+  let output = retry.output(|| flaky_command(&script)).unwrap();
+  assert_eq!(output.status.code(), Some(1));
+  let mut attempts = String::new();
+  File::open(&attempts_log).unwrap().read_to_string(&mut attempts).unwrap();
+  assert_eq!(attempts.lines().count(), 1);
+  fs::remove_file(&attempts_log).unwrap();
+}
+
+#[test]
+fn converter_chain_runs_in_order() {
+  let backend = Backend::testdb();
+  if backend.needs_init().unwrap() {
+    backend.setup_task_tables().unwrap();
+  }
+  let service = Service {
+    id : None,
+    name : "converter_chain_test_service".to_string(),
+    version : 0.1,
+    inputformat : "tex".to_string(),
+    outputformat : "html".to_string(),
+    inputconverter : Some("import".to_string()),
+    complex : true
+  };
+  let chain = ConverterChain::resolve(&backend, &service).unwrap();
+  assert_eq!(chain.input_converter.as_ref().map(|converter| converter.name.clone()), Some("import".to_string()));
+
+  let mut stages_log = env::temp_dir();
+  stages_log.push("cortex_converter_chain_stages");
+  let _ = fs::remove_file(&stages_log);
+  let log_path = stages_log.to_str().unwrap().to_string();
+  let input = Path::new("/converter_chain/entry.zip");
+  let output = chain.output(input,
+    |converter, input, output| flaky_command(&format!("echo converter {} {} {} >> {}",
+      converter.name, input.display(), output.display(), log_path)),
+    |input| flaky_command(&format!("echo main {} >> {}", input.display(), log_path))).unwrap();
+  assert!(output.status.success());
+  let mut stages = String::new();
+  File::open(&stages_log).unwrap().read_to_string(&mut stages).unwrap();
+  assert_eq!(stages.lines().collect::<Vec<_>>(), vec![
+    "converter import /converter_chain/entry.zip /converter_chain/import.zip",
+    "main /converter_chain/import.zip"]);
+
+  // A failed converter stage is reported as such, and the main conversion never runs
+  fs::remove_file(&stages_log).unwrap();
+  match chain.output(input, |_, _, _| flaky_command("exit 1"),
+    |input| flaky_command(&format!("echo main {} >> {}", input.display(), log_path))) {
+    Err(CortexError::Invalid(message)) => assert!(message.starts_with("input converter import failed")),
+    _ => panic!("expected the converter stage to fail")
+  }
+  assert!(File::open(&stages_log).is_err());
+}
+
+#[test]
+fn command_worker_converts_through_chain() {
+  let backend = Backend::testdb();
+  if backend.needs_init().unwrap() {
+    backend.setup_task_tables().unwrap();
+  }
+  let corpus = backend.add(
+    Corpus {
+      id : None,
+      name : "command worker corpus".to_string(),
+      path : "tests/data/".to_string(),
+      complex : true
+    }).unwrap();
+  backend.add(
+    Service {
+      id : None,
+      name : "command_worker_test_converter".to_string(),
+      version : 0.1,
+      inputformat : "tex".to_string(),
+      outputformat : "tex".to_string(),
+      inputconverter : Some("import".to_string()),
+      complex : true
+    }).unwrap();
+  let service = backend.add(
+    Service {
+      id : None,
+      name : "command_worker_test_service".to_string(),
+      version : 0.1,
+      inputformat : "tex".to_string(),
+      outputformat : "tex".to_string(),
+      inputconverter : Some("command_worker_test_converter".to_string()),
+      complex : true
+    }).unwrap();
+  let mut entry_path = Importer::cwd();
+  entry_path.push("tests/data/1206.5501/1206.5501.zip");
+  let task = backend.add(
+    Task {
+      id : None,
+      entry : entry_path.to_str().unwrap().to_string(),
+      serviceid : service.id.unwrap(),
+      corpusid : corpus.id.unwrap(),
+      status : TaskStatus::TODO.raw(),
+      metadata : None
+    }).unwrap();
+
+  let mut stages_log = env::temp_dir();
+  stages_log.push("cortex_command_worker_stages");
+  let _ = fs::remove_file(&stages_log);
+  let log_path = stages_log.to_str().unwrap().to_string();
+  let manager_thread = thread::spawn(move || {
+    let manager = TaskManager {
+      source_port : 5665,
+      result_port : 5666,
+      queue_size : 100,
+      message_size : 100000,
+      backend_address : TEST_DB_ADDRESS.to_string(),
+      maintenance_interval : 60,
+      task_timeout : 3600,
+      send_payload : true,
+      acknowledge_results : false,
+      // Keep the test data directory free of results
+      discard_output : true,
+      ventilator_stats : Arc::new(VentilatorStats::default())
+    };
+    assert!(manager.start(Some(1)).is_ok());
+  });
+  // Both stages pass the entry archive along, logging that they ran
+  let worker = CommandWorker {
+    service : "command_worker_test_service".to_string(),
+    version : 0.1,
+    message_size : 100000,
+    source : "tcp://localhost:5665".to_string(),
+    sink : "tcp://localhost:5666".to_string(),
+    backend_address : TEST_DB_ADDRESS.to_string(),
+    program : "sh".to_string(),
+    args : vec!["-c".to_string(), format!("echo main >> {}; cp \"$0\" \"$1\"", log_path)],
+    converter_program : Some("sh".to_string()),
+    converter_args : vec!["-c".to_string(), format!("echo converter >> {}; cp \"$0\" \"$1\"", log_path)],
+    max_internal_retries : 0
+  };
+  assert!(worker.start(Some(1)).is_ok());
+  assert!(manager_thread.join().is_ok());
+
+  let mut stages = String::new();
+  File::open(&stages_log).unwrap().read_to_string(&mut stages).unwrap();
+  assert_eq!(stages.lines().collect::<Vec<_>>(), vec!["converter", "main"]);
+  // The converted archive reached the Task store, without a cortex.log, so as a fatal result listing its .tex artifact
+  assert_eq!(backend.sync(&task).unwrap().status, TaskStatus::Fatal.raw());
+  let artifact_formats : Option<String> = backend.connection.prepare("SELECT artifact_formats FROM tasks WHERE taskid = $1").unwrap()
+    .query(&[&task.id.unwrap()]).unwrap().get(0).get(0);
+  assert_eq!(artifact_formats, Some("tex".to_string()));
+  fs::remove_file(&stages_log).unwrap();
+}
+
+#[test]
+fn command_worker_retries_transient_failures() {
+  let backend = Backend::testdb();
+  if backend.needs_init().unwrap() {
+    backend.setup_task_tables().unwrap();
+  }
+  let corpus = backend.add(
+    Corpus {
+      id : None,
+      name : "retrying command worker corpus".to_string(),
+      path : "tests/data/".to_string(),
+      complex : true
+    }).unwrap();
+  let service = backend.add(
+    Service {
+      id : None,
+      name : "retrying_command_worker_test_service".to_string(),
+      version : 0.1,
+      inputformat : "tex".to_string(),
+      outputformat : "tex".to_string(),
+      inputconverter : None,
+      complex : true
+    }).unwrap();
+  let mut entry_path = Importer::cwd();
+  entry_path.push("tests/data/1206.5501/1206.5501.zip");
+  let task = backend.add(
+    Task {
+      id : None,
+      entry : entry_path.to_str().unwrap().to_string(),
+      serviceid : service.id.unwrap(),
+      corpusid : corpus.id.unwrap(),
+      status : TaskStatus::TODO.raw(),
+      metadata : None
+    }).unwrap();
+
+  let mut marker = env::temp_dir();
+  marker.push("cortex_command_worker_retry_marker");
+  let _ = fs::remove_file(&marker);
+  let manager_thread = thread::spawn(move || {
+    let manager = TaskManager {
+      source_port : 5667,
+      result_port : 5668,
+      queue_size : 100,
+      message_size : 100000,
+      backend_address : TEST_DB_ADDRESS.to_string(),
+      maintenance_interval : 60,
+      task_timeout : 3600,
+      send_payload : true,
+      acknowledge_results : false,
+      discard_output : true,
+      ventilator_stats : Arc::new(VentilatorStats::default())
+    };
+    assert!(manager.start(Some(1)).is_ok());
+  });
+  // Fails with EX_TEMPFAIL on the first attempt, passes the entry archive along on the next
+  let worker = CommandWorker {
+    service : "retrying_command_worker_test_service".to_string(),
+    version : 0.1,
+    message_size : 100000,
+    source : "tcp://localhost:5667".to_string(),
+    sink : "tcp://localhost:5668".to_string(),
+    backend_address : TEST_DB_ADDRESS.to_string(),
+    program : "sh".to_string(),
+    args : vec!["-c".to_string(),
+      format!("if [ -e {0} ]; then cp \"$0\" \"$1\"; else touch {0}; exit 75; fi", marker.to_str().unwrap())],
+    converter_program : None,
+    converter_args : Vec::new(),
+    max_internal_retries : 1
+  };
+  assert!(worker.start(Some(1)).is_ok());
+  assert!(manager_thread.join().is_ok());
+
+  // The retried conversion delivered its archive, listing the .tex artifact
+  let artifact_formats : Option<String> = backend.connection.prepare("SELECT artifact_formats FROM tasks WHERE taskid = $1").unwrap()
+    .query(&[&task.id.unwrap()]).unwrap().get(0).get(0);
+  assert_eq!(artifact_formats, Some("tex".to_string()));
+  fs::remove_file(&marker).unwrap();
+}